@@ -7,6 +7,7 @@ use std::path::Path;
 use std::str::FromStr;
 use nom::IResult;
 use nom::character::complete::digit1;
+use nom::combinator::map_res;
 use thiserror::Error;
 
 // The output is wrapped in a Result to allow matching on errors
@@ -17,20 +18,16 @@ pub fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
     Ok(io::BufReader::new(file).lines())
 }
 
+// digits that don't fit T are a parse error rather than a panic
 pub fn number<T: FromStr + Default>(input: &str) -> IResult<&str, T> {
-    Ok(digit1(input).map(|(remaining, number)| {
-        match number.parse() {
-            Ok(res) => (remaining, res),
-            Err(e) => panic!(), // TODO: better error handling?
-        }
-    })?)
+    map_res(digit1, |n: &str| n.parse::<T>())(input)
 }
 
 #[derive(Error, Debug)]
 pub enum AoCError {
-    #[error("input file had unexpected or invalid format")]
+    #[error("input file had unexpected or invalid format: {0}")]
     InputFormatError(&'static str),
-    #[error("input value had unexpected or invalid format")]
+    #[error("input value had unexpected or invalid format: {0}")]
     InputValueError(String)
 }
 
@@ -39,3 +36,279 @@ impl From<ParseIntError> for AoCError {
         AoCError::InputValueError(format!("input value is invalid or unexpected: {}", value))
     }
 }
+
+// xorshift so generated test inputs are repeatable without pulling in a rand dependency
+#[cfg(test)]
+pub struct XorShift(pub u64);
+
+#[cfg(test)]
+impl XorShift {
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+// a horizontal run of cells within a single grid row; end is inclusive
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Span {
+    pub row: usize,
+    pub start: usize,
+    pub end: usize
+}
+
+impl Span {
+    pub fn contains(&self, row: usize, col: usize) -> bool {
+        row == self.row && col >= self.start && col <= self.end
+    }
+}
+
+const NEIGHBOURS4: [(isize, isize); 4] = [(-1, 0), (0, -1), (0, 1), (1, 0)];
+const NEIGHBOURS8: [(isize, isize); 8] = [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)];
+
+// distance rule used to decide whether two cells are neighbours
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Metric {
+    Chebyshev,  // king moves, diagonals included
+    Manhattan,  // taxicab distance
+    Orthogonal  // same row or column only
+}
+
+impl Metric {
+    pub fn within(&self, dr: isize, dc: isize, radius: usize) -> bool {
+        let (ar, ac) = (dr.unsigned_abs(), dc.unsigned_abs());
+        match self {
+            Metric::Chebyshev => ar.max(ac) <= radius,
+            Metric::Manhattan => ar + ac <= radius,
+            Metric::Orthogonal => (ar == 0 || ac == 0) && ar.max(ac) <= radius
+        }
+    }
+}
+
+// rectangular grid stored row-major; positions are (row, col)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Grid<T> {
+    width: usize,
+    height: usize,
+    cells: Vec<T>
+}
+
+impl<T> Grid<T> {
+    pub fn new() -> Self {
+        Grid{width: 0, height: 0, cells: Vec::new()}
+    }
+
+    pub fn filled(width: usize, height: usize, value: T) -> Self where T: Clone {
+        Grid{width, height, cells: vec![value; width * height]}
+    }
+
+    // every line must produce the same number of cells
+    pub fn from_lines<S: AsRef<str>>(lines: impl IntoIterator<Item = S>, f: impl Fn(char) -> T) -> Result<Self, AoCError> {
+        let mut ret = Grid::new();
+        for line in lines {
+            ret.push_row(line.as_ref().chars().map(&f).collect())?;
+        }
+        Ok(ret)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn push_row(&mut self, row: Vec<T>) -> Result<(), AoCError> {
+        if self.height == 0 {
+            self.width = row.len();
+        } else if row.len() != self.width {
+            return Err(AoCError::InputValueError(format!(
+                "grid row {} has width {}, expected {}", self.height + 1, row.len(), self.width)))
+        }
+        self.cells.extend(row);
+        self.height += 1;
+        Ok(())
+    }
+
+    pub fn drop_first_row(&mut self) {
+        if self.height > 0 {
+            self.cells.drain(..self.width);
+            self.height -= 1;
+        }
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> Option<&T> {
+        if row < self.height && col < self.width {
+            self.cells.get(row * self.width + col)
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self, row: usize, col: usize) -> Option<&mut T> {
+        if row < self.height && col < self.width {
+            self.cells.get_mut(row * self.width + col)
+        } else {
+            None
+        }
+    }
+
+    pub fn row(&self, row: usize) -> Option<&[T]> {
+        if row < self.height {
+            Some(&self.cells[row * self.width..(row + 1) * self.width])
+        } else {
+            None
+        }
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> {
+        (0..self.height).map(move |r| &self.cells[r * self.width..(r + 1) * self.width])
+    }
+
+    pub fn column(&self, col: usize) -> impl Iterator<Item = &T> {
+        let height = if col < self.width { self.height } else { 0 };
+        (0..height).map(move |r| &self.cells[r * self.width + col])
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = (usize, usize)> {
+        let width = self.width;
+        (0..self.height).flat_map(move |r| (0..width).map(move |c| (r, c)))
+    }
+
+    fn offsets(&self, row: usize, col: usize, deltas: &'static [(isize, isize)]) -> impl Iterator<Item = (usize, usize)> {
+        let (height, width) = (self.height, self.width);
+        deltas.iter().filter_map(move |(dr, dc)| {
+            let r = row.checked_add_signed(*dr)?;
+            let c = col.checked_add_signed(*dc)?;
+            if r < height && c < width { Some((r, c)) } else { None }
+        })
+    }
+
+    pub fn neighbours4(&self, row: usize, col: usize) -> impl Iterator<Item = (usize, usize)> {
+        self.offsets(row, col, &NEIGHBOURS4)
+    }
+
+    pub fn neighbours8(&self, row: usize, col: usize) -> impl Iterator<Item = (usize, usize)> {
+        self.offsets(row, col, &NEIGHBOURS8)
+    }
+
+    // all in-bounds cells within radius of (row, col) under the metric, excluding the cell itself
+    pub fn neighbourhood(&self, row: usize, col: usize, radius: usize, metric: Metric) -> impl Iterator<Item = (usize, usize)> {
+        let (height, width) = (self.height, self.width);
+        let r = radius as isize;
+        (-r..=r).flat_map(move |dr| (-r..=r).map(move |dc| (dr, dc)))
+            .filter(move |(dr, dc)| (*dr, *dc) != (0, 0) && metric.within(*dr, *dc, radius))
+            .filter_map(move |(dr, dc)| {
+                let nr = row.checked_add_signed(dr)?;
+                let nc = col.checked_add_signed(dc)?;
+                if nr < height && nc < width { Some((nr, nc)) } else { None }
+            })
+    }
+
+    // maximal horizontal runs of cells in one row that satisfy the predicate
+    pub fn row_runs(&self, row: usize, pred: impl Fn(&T) -> bool) -> Vec<Span> {
+        let mut ret: Vec<Span> = Vec::new();
+        let mut start: Option<usize> = None;
+        for (col, cell) in self.row(row).unwrap_or_default().iter().enumerate() {
+            match (pred(cell), start) {
+                (true, None) => start = Some(col),
+                (false, Some(s)) => {
+                    ret.push(Span{row, start: s, end: col - 1});
+                    start = None
+                },
+                _ => {}
+            }
+        }
+        if let Some(s) = start {
+            ret.push(Span{row, start: s, end: self.width - 1});
+        }
+        ret
+    }
+
+    pub fn runs(&self, pred: impl Fn(&T) -> bool) -> Vec<Span> {
+        (0..self.height).flat_map(|r| self.row_runs(r, &pred)).collect()
+    }
+
+    pub fn span_cells(&self, span: &Span) -> &[T] {
+        &self.row(span.row).unwrap_or_default()[span.start..=span.end]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{AoCError, Grid, Metric, Span, number};
+
+    #[test]
+    fn test_number() {
+        assert_eq!(Ok((" x", 255u8)), number::<u8>("255 x"));
+        assert!(number::<u8>("256").is_err());
+        assert!(number::<u64>("x1").is_err());
+    }
+
+    #[test]
+    fn test_error_display() {
+        let err = AoCError::InputValueError("line 3 has width 9, expected 10".to_string());
+        assert_eq!("input value had unexpected or invalid format: line 3 has width 9, expected 10", err.to_string());
+        assert_eq!("input file had unexpected or invalid format: found no seeds", AoCError::InputFormatError("found no seeds").to_string());
+    }
+
+    #[test]
+    fn test_grid_loading() {
+        let grid = Grid::from_lines(["ab.", "..c"], |c| c).unwrap();
+        assert_eq!(3, grid.width());
+        assert_eq!(2, grid.height());
+        assert_eq!(Some(&'c'), grid.get(1, 2));
+        assert_eq!(None, grid.get(2, 0));
+        assert_eq!(None, grid.get(0, 3));
+        assert_eq!(vec!['b', '.'], grid.column(1).copied().collect::<Vec<_>>());
+        assert_eq!(vec![&['a', 'b', '.'][..], &['.', '.', 'c'][..]], grid.rows().collect::<Vec<_>>());
+
+        let ragged = Grid::from_lines(["ab.", "..", "..."], |c| c);
+        assert!(ragged.is_err());
+    }
+
+    #[test]
+    fn test_grid_neighbours() {
+        let grid = Grid::from_lines(["...", "...", "..."], |c| c).unwrap();
+        assert_eq!(3, grid.neighbours8(0, 0).count());
+        assert_eq!(8, grid.neighbours8(1, 1).count());
+        assert_eq!(2, grid.neighbours4(2, 2).count());
+        assert_eq!(vec![(0, 1), (1, 0), (1, 2), (2, 1)], grid.neighbours4(1, 1).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_grid_neighbourhood() {
+        let grid = Grid::from_lines([".....", ".....", ".....", ".....", "....."], |c| c).unwrap();
+        let sorted = |metric| {
+            let mut ret: Vec<_> = grid.neighbourhood(1, 1, 1, metric).collect();
+            ret.sort();
+            ret
+        };
+        assert_eq!(grid.neighbours8(1, 1).collect::<Vec<_>>(), sorted(Metric::Chebyshev));
+        assert_eq!(grid.neighbours4(1, 1).collect::<Vec<_>>(), sorted(Metric::Manhattan));
+        assert_eq!(grid.neighbours4(1, 1).collect::<Vec<_>>(), sorted(Metric::Orthogonal));
+
+        assert_eq!(24, grid.neighbourhood(2, 2, 2, Metric::Chebyshev).count());
+        assert_eq!(12, grid.neighbourhood(2, 2, 2, Metric::Manhattan).count());
+        assert_eq!(8, grid.neighbourhood(2, 2, 2, Metric::Orthogonal).count());
+        assert_eq!(8, grid.neighbourhood(0, 0, 2, Metric::Chebyshev).count());
+        assert_eq!(0, grid.neighbourhood(2, 2, 0, Metric::Chebyshev).count());
+    }
+
+    #[test]
+    fn test_grid_runs() {
+        let grid = Grid::from_lines(["12..3", "..456"], |c| c).unwrap();
+        let runs = grid.runs(|c| c.is_ascii_digit());
+        assert_eq!(vec![
+            Span{row: 0, start: 0, end: 1},
+            Span{row: 0, start: 4, end: 4},
+            Span{row: 1, start: 2, end: 4}], runs);
+        assert_eq!(&['4', '5', '6'], grid.span_cells(&runs[2]));
+    }
+}
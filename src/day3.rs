@@ -1,27 +1,221 @@
-use std::cmp::{max, min, Ordering};
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::IsTerminal;
 use crate::common;
+use crate::common::{AoCError, Grid, Metric, Span};
 
-#[derive(Clone, Default)]
-struct LineProc {
-    nums: Vec<(u32, usize, usize)>,
-    symbol_positions: HashSet<usize>
-}
-
+// decides which schematic characters count as symbols. closures (including ones capturing
+// configuration) and plain fns implement it directly
 pub trait SelectSymbol {
     fn select(&self, c: char) -> bool;
 }
 
-struct Section {
-    select_func: fn(c: char) -> bool,
+impl<F: Fn(char) -> bool> SelectSymbol for F {
+    fn select(&self, c: char) -> bool {
+        self(c)
+    }
+}
+
+// the puzzle definition: anything that is not a digit or '.'
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AnySymbol;
+
+impl SelectSymbol for AnySymbol {
+    fn select(&self, c: char) -> bool {
+        is_symbol(c)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SymbolSet(Vec<char>);
+
+impl SymbolSet {
+    pub fn new(symbols: &str) -> Self {
+        SymbolSet(symbols.chars().collect())
+    }
+}
+
+impl SelectSymbol for SymbolSet {
+    fn select(&self, c: char) -> bool {
+        self.0.contains(&c)
+    }
+}
+
+// a regex-style bracket expression such as "[*#]", "[^.0-9]" or "[\d]"; supports
+// literal characters, ranges, \d, \w and escapes of "\\", "]", "-" and "^"
+#[derive(Clone, Debug, PartialEq)]
+pub struct CharClass {
+    negated: bool,
+    ranges: Vec<(char, char)>
+}
+
+impl CharClass {
+    pub fn parse(pattern: &str) -> Result<Self, AoCError> {
+        let invalid = || AoCError::InputValueError(format!("invalid character class: {}", pattern));
+        let body = pattern.strip_prefix('[').and_then(|p| p.strip_suffix(']')).ok_or_else(invalid)?;
+        let (negated, body) = match body.strip_prefix('^') {
+            Some(rest) => (true, rest),
+            None => (false, body)
+        };
+
+        let mut ranges: Vec<(char, char)> = Vec::new();
+        let mut chars = body.chars().peekable();
+        while let Some(c) = chars.next() {
+            let lo = match c {
+                '\\' => match chars.next() {
+                    Some('d') => { ranges.push(('0', '9')); continue },
+                    Some('w') => {
+                        ranges.extend([('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')]);
+                        continue
+                    },
+                    Some(e @ ('\\' | ']' | '-' | '^')) => e,
+                    _ => return Err(invalid())
+                },
+                ']' => return Err(invalid()),
+                c => c
+            };
+            let mut range_end = chars.clone();
+            match (range_end.next(), range_end.next()) {
+                (Some('-'), Some(hi)) if hi != '\\' => {
+                    if hi < lo {
+                        return Err(invalid())
+                    }
+                    ranges.push((lo, hi));
+                    chars = range_end;
+                },
+                _ => ranges.push((lo, lo))
+            }
+        }
+        if ranges.is_empty() {
+            return Err(invalid())
+        }
+        Ok(CharClass{negated, ranges})
+    }
+}
+
+impl SelectSymbol for CharClass {
+    fn select(&self, c: char) -> bool {
+        self.ranges.iter().any(|(lo, hi)| c >= *lo && c <= *hi) != self.negated
+    }
+}
+
+// a part number located by its schematic row and starting column
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Part {
+    row: usize,
+    col: usize,
+    value: u32
+}
+
+#[derive(Debug, PartialEq)]
+struct Gear {
+    row: usize,
+    col: usize,
+    parts: Vec<Part>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PartCount {
+    Exactly(usize),
+    AtLeast(usize)
+}
+
+impl PartCount {
+    fn accepts(&self, cnt: usize) -> bool {
+        match self {
+            PartCount::Exactly(n) => cnt == *n,
+            PartCount::AtLeast(n) => cnt >= *n
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Combine {
+    Product,
+    Sum,
+    Max
+}
+
+impl Combine {
+    // None when the value doesn't fit in u64. a hub with no parts is worth nothing under every
+    // rule, so an empty product is 0 rather than 1
+    fn apply(&self, parts: &[Part]) -> Option<u64> {
+        let mut values = parts.iter().map(|p| p.value as u64);
+        if parts.is_empty() {
+            return Some(0)
+        }
+        match self {
+            Combine::Product => values.try_fold(1u64, |acc, v| acc.checked_mul(v)),
+            Combine::Sum => values.try_fold(0u64, |acc, v| acc.checked_add(v)),
+            Combine::Max => values.max()
+        }
+    }
+}
+
+// a group of symbols and the numbers touching them, linked through shared members
+#[derive(Debug, PartialEq)]
+struct Cluster {
+    parts: Vec<Part>,
+    symbols: Vec<(usize, usize, char)>,
+    top_left: (usize, usize),
+    bottom_right: (usize, usize)
+}
+
+impl Cluster {
+    fn sum(&self) -> u64 {
+        self.parts.iter().map(|p| p.value as u64).sum()
+    }
+}
+
+// how far a symbol may be from a number and still count as touching it
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Adjacency {
+    radius: usize,
+    metric: Metric
+}
+
+impl Default for Adjacency {
+    fn default() -> Self {
+        Adjacency{radius: 1, metric: Metric::Chebyshev}
+    }
+}
+
+// what makes a symbol a gear and how its adjacent parts are combined into a value
+#[derive(Clone, Debug)]
+struct GearRule<S: SelectSymbol> {
+    hub: S,
+    count: PartCount,
+    combine: Combine,
+    adjacency: Adjacency
+}
+
+impl Default for GearRule<SymbolSet> {
+    // the puzzle rule: a '*' touching exactly two parts, valued by their product
+    fn default() -> Self {
+        GearRule{hub: SymbolSet::new("*"), count: PartCount::Exactly(2), combine: Combine::Product, adjacency: Adjacency::default()}
+    }
+}
+
+impl Gear {
+    fn value(&self, combine: Combine) -> Result<u64, AoCError> {
+        combine.apply(&self.parts).ok_or_else(|| AoCError::InputValueError(format!(
+            "gear at row {} column {} overflows u64", self.row + 1, self.col + 1)))
+    }
+}
+
+// sliding window over the schematic; only the rows needed to evaluate `current` are kept,
+// which is the adjacency radius either side of it
+struct Section<S: SelectSymbol> {
+    selector: S,
+    adjacency: Adjacency,
+    window: Grid<char>,
+    first_row: usize, // schematic row held in window row 0
+    pushed: usize,    // rows pushed so far, including trailing blanks
     width: usize,
-    preceding: LineProc,
-    current: LineProc,
-    next: LineProc
+    ended: bool
 }
 
 fn is_symbol(c: char) -> bool {
-    if c.is_digit(10) || c == '.' {
+    if c.is_ascii_digit() || c == '.' {
         return false
     }
     true
@@ -34,123 +228,144 @@ fn is_star(c: char) -> bool {
     false
 }
 
-impl SelectSymbol for Section {
-    fn select(&self, c: char) -> bool {
-        if c.is_digit(10) || c == '.' {
-            return false
-        }
-        true
+fn is_digit(c: &char) -> bool {
+    c.is_ascii_digit()
+}
+
+// columns are byte offsets, which is why only printable ascii is accepted
+fn validate_line(line_num: usize, line: &str, width: usize) -> Result<(), AoCError> {
+    if let Some(pos) = line.bytes().position(|b| !b.is_ascii_graphic()) {
+        return Err(AoCError::InputValueError(format!(
+            "schematic line {} has unsupported character at column {}", line_num, pos + 1)))
+    }
+    if line.len() != width {
+        return Err(AoCError::InputValueError(format!(
+            "schematic line {} has width {}, expected {}", line_num, line.len(), width)))
     }
+    Ok(())
 }
 
-impl Section {
+fn load_schematic<S: AsRef<str>>(lines: impl IntoIterator<Item = S>) -> Result<Grid<char>, AoCError> {
+    let mut ret = Grid::new();
+    for (idx, line) in lines.into_iter().enumerate() {
+        let line = line.as_ref();
+        let width = if idx == 0 { line.len() } else { ret.width() };
+        validate_line(idx + 1, line, width)?;
+        ret.push_row(line.bytes().map(char::from).collect())?;
+    }
+    Ok(ret)
+}
 
-    fn new(sf: fn(c: char) -> bool) -> Self {
+fn span_value(window: &Grid<char>, span: &Span) -> u32 {
+    window.span_cells(span).iter().fold(0, |acc, c| acc * 10 + c.to_digit(10).unwrap())
+}
+
+impl<S: SelectSymbol> Section<S> {
+
+    fn new(selector: S) -> Self {
+        Section::with_adjacency(selector, Adjacency::default())
+    }
+
+    fn with_adjacency(selector: S, adjacency: Adjacency) -> Self {
         Section{
-            select_func: sf,
+            selector,
+            adjacency,
+            window: Grid::new(),
+            first_row: 0,
+            pushed: 0,
             width: 0,
-            preceding: LineProc::default(),
-            current: LineProc::default(),
-            next: LineProc::default(),
+            ended: false
         }
     }
 
-    fn process_line(&self, line: &str) -> LineProc {
-        let mut nums: Vec<(u32, usize, usize)> = Vec::new();
-        let mut got_num = false;
-
-        let mut symbol_positions: Vec<usize> = Vec::new();
-
-        for (pos, c) in line.chars().enumerate() {
-            if c.is_digit(10) {
-                let num: u32 = c.to_string().parse().unwrap();
-                if got_num {
-                    let idx = nums.len() - 1;
-                    nums[idx].0 *= 10;
-                    nums[idx].0 += num;
-                } else {
-                    got_num = true;
-                    nums.push((num, pos, 0));
-                }
-            } else {
-                if got_num {
-                    let idx = nums.len() - 1;
-                    nums[idx].2 = pos - 1;
-                    got_num = false;
-                }
-            }
-            // if self.select(c) {
-            if (self.select_func)(c) {
-                symbol_positions.push(pos);
+    // an empty line is treated as a blank row past the end of the schematic, used to flush the window
+    fn push(&mut self, line: &str) -> Result<(), AoCError> {
+        let line_num = self.pushed + 1;
+        if line.is_empty() {
+            self.ended = true;
+        } else if self.ended {
+            return Err(AoCError::InputValueError(format!("schematic line {} follows a blank line", line_num)))
+        } else {
+            if self.pushed == 0 {
+                self.width = line.len();
             }
+            validate_line(line_num, line, self.width)?;
+            self.window.push_row(line.bytes().map(char::from).collect())?;
         }
-        if got_num {
-            let idx = nums.len() - 1;
-            nums[idx].2 = line.len() - 1;
+        self.pushed += 1;
+        while self.window.height() > 0 && self.first_row + self.adjacency.radius < self.current().unwrap_or(0) {
+            self.window.drop_first_row();
+            self.first_row += 1;
         }
+        Ok(())
+    }
 
-        nums.sort_by(|a, b| a.1.cmp(&b.1)); // we sort the nums by their position tuple
-        LineProc{
-            nums,
-            symbol_positions: symbol_positions.iter().map(|x| *x).collect(),
-        }
+    // number of blank rows to push after the last line so every row gets evaluated
+    fn lookahead(&self) -> usize {
+        self.adjacency.radius
     }
 
-    fn push(&self, line: &str) -> Self {
-        Section{
-            select_func: self.select_func,
-            width: max(self.width, line.len()), // TODO: validate constant?
-            preceding: self.current.clone(),
-            current: self.next.clone(),
-            next: self.process_line(line),
+    // the schematic row being evaluated - radius rows behind the most recently pushed row
+    fn current(&self) -> Option<usize> {
+        let cur = self.pushed.checked_sub(self.adjacency.radius + 1)?;
+        if cur < self.first_row + self.window.height() { Some(cur) } else { None }
+    }
+
+    // window row index of the current row
+    fn current_window_row(&self) -> Option<usize> {
+        Some(self.current()? - self.first_row)
+    }
+
+    fn current_nums(&self) -> Vec<Span> {
+        match self.current_window_row() {
+            Some(row) => self.window.row_runs(row, is_digit),
+            None => Vec::new()
         }
     }
 
+    fn neighbourhood(&self, row: usize, col: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.window.neighbourhood(row, col, self.adjacency.radius, self.adjacency.metric)
+    }
+
+    fn part(&self, span: &Span) -> Part {
+        Part{row: self.first_row + span.row, col: span.start, value: span_value(&self.window, span)}
+    }
+
+    fn find_adjacent_parts(&self) -> Vec<Part> {
+        self.current_nums().iter().filter(|span| {
+            (span.start..=span.end).any(|col| {
+                self.neighbourhood(span.row, col)
+                    .any(|(r, c)| self.selector.select(*self.window.get(r, c).unwrap()))
+            })
+        }).map(|span| self.part(span)).collect()
+    }
+
     fn find_adjacent_nums(&self) -> Vec<u32> {
-        let ret = self.current.nums.iter().enumerate().filter(|(idx, (num, range_lo, range_hi))|{
-            let rl = if *range_lo > 0 { range_lo - 1 } else { *range_lo };
-            let rh = if *range_hi < (self.width - 1) { range_hi + 1 } else { *range_hi };
-            for rx in rl..=rh {
-                for sp in [&self.preceding.symbol_positions, &self.current.symbol_positions, &self.next.symbol_positions] {
-                    if sp.contains(&rx) {
-                        return true
-                    }
-                }
+        self.find_adjacent_parts().iter().map(|p| p.value).collect()
+    }
+
+    // adjacent numbers are keyed by position so two equal part numbers on one star both count
+    fn find_gears(&self, count: PartCount) -> Vec<Gear> {
+        let mut found_gears: Vec<Gear> = Vec::new();
+        let row = match self.current_window_row() {
+            Some(row) => row,
+            None => return found_gears
+        };
+        let row_nums: Vec<Vec<Span>> = (0..self.window.height())
+            .map(|r| self.window.row_runs(r, is_digit)).collect();
+        for col in 0..self.window.width() {
+            if !self.selector.select(*self.window.get(row, col).unwrap()) {
+                continue
             }
-            false
-        });
-        ret.map(|(_, (num, _, _))| *num ).collect()
-    }
-
-    fn star_search_range(&self, pos: usize) -> (usize, usize) {
-        (if pos == 0 {0} else {pos-1},
-          if pos == self.width-1 {self.width-1} else {pos+1})
-    }
-
-    fn find_gears(&self) -> Vec<(u32, u32)> {
-        let mut found_gears: Vec<(u32, u32)> = Vec::new();
-        for pos in self.current.symbol_positions.iter() {
-            let mut star_nums: HashSet<u32> = HashSet::new();
-            let (start_pos, end_pos) = self.star_search_range(*pos);
-            for seek in start_pos..=end_pos {
-                for nums in [&self.preceding.nums, &self.current.nums, &self.next.nums] {
-                    match nums.binary_search_by(|probe| {
-                        if seek >= probe.1 && seek <= probe.2 {
-                            Ordering::Equal
-                        } else if probe.1 < seek {
-                            Ordering::Less
-                        } else {
-                            Ordering::Greater
-                        }
-                    }) {
-                        Ok(idx) => {star_nums.insert(nums[idx].0);},
-                        _ => {}
-                    }
+            let mut star_nums: BTreeSet<Span> = BTreeSet::new();
+            for (r, c) in self.neighbourhood(row, col) {
+                if let Some(span) = row_nums[r].iter().find(|s| s.contains(r, c)) {
+                    star_nums.insert(*span);
                 }
             }
-            if star_nums.len() == 2 {
-                let c: Vec<&u32> = star_nums.iter().collect();
-                found_gears.push((*c[0], *c[1]))
+            if count.accepts(star_nums.len()) {
+                let parts: Vec<Part> = star_nums.iter().map(|span| self.part(span)).collect();
+                found_gears.push(Gear{row: self.first_row + row, col, parts})
             }
         }
         found_gears
@@ -160,47 +375,254 @@ impl Section {
 pub fn do_day3() {
     if let Ok(lines) = common::read_lines("./data/day3input.txt") {
         let lines_iter = lines.map(|l| l.unwrap()).into_iter();
-        let total = process_lines_day3_part2(lines_iter);
-        println!("final sum: {}", total);
+        match process_lines_day3_part2(lines_iter) {
+            Ok(total) => println!("final sum: {}", total),
+            Err(e) => println!("failed to process schematic: {}", e)
+        }
     }
 }
 
-fn process_lines_day3(lines : impl std::iter::Iterator<Item = String>) -> u32 {
+fn process_lines_day3(lines : impl std::iter::Iterator<Item = String>) -> Result<u32, AoCError> {
+    process_lines_parts(lines, AnySymbol, Adjacency::default())
+}
+
+fn process_lines_parts<S: SelectSymbol>(lines : impl std::iter::Iterator<Item = String>, selector: S, adjacency: Adjacency) -> Result<u32, AoCError> {
     let mut total = 0;
-    let mut sec = Section::new(is_symbol);
+    let mut sec = Section::with_adjacency(selector, adjacency);
     for line in lines {
-        sec = sec.push(line.as_str());
+        sec.push(line.as_str())?;
+        let ret = sec.find_adjacent_nums();
+        let sum = ret.iter().fold(0, |acc, num| acc + num);
+        total += sum;
+    }
+    for _ in 0..sec.lookahead() {
+        sec.push("")?;
         let ret = sec.find_adjacent_nums();
         let sum = ret.iter().fold(0, |acc, num| acc + num);
         total += sum;
     }
-    sec = sec.push("");
-    let ret = sec.find_adjacent_nums();
-    let sum = ret.iter().fold(0, |acc, num| acc + num);
-    total += sum;
-    total
+    Ok(total)
 }
 
-fn process_lines_day3_part2(lines : impl std::iter::Iterator<Item = String>) -> u32 {
-    let mut total = 0;
-    let mut sec = Section::new(is_star);
+fn find_root(roots: &mut [usize], idx: usize) -> usize {
+    let mut root = idx;
+    while roots[root] != root {
+        root = roots[root];
+    }
+    let mut cur = idx;
+    while roots[cur] != root {
+        let next = roots[cur];
+        roots[cur] = root;
+        cur = next;
+    }
+    root
+}
+
+// connected components over the whole schematic. every number and every selected symbol is a
+// node, and a number is joined to each symbol within its adjacency. numbers never join other
+// numbers directly, so a cluster's parts are exactly the numbers that touch one of its symbols
+fn find_clusters<S: SelectSymbol>(grid: &Grid<char>, selector: &S, adjacency: Adjacency) -> Vec<Cluster> {
+    let mut nodes: Vec<Span> = grid.runs(is_digit);
+    let numbers = nodes.len();
+    nodes.extend(grid.positions()
+        .filter(|(r, c)| selector.select(*grid.get(*r, *c).unwrap()))
+        .map(|(row, col)| Span{row, start: col, end: col}));
+
+    let mut labels: Grid<Option<usize>> = Grid::filled(grid.width(), grid.height(), None);
+    for (idx, span) in nodes.iter().enumerate() {
+        for col in span.start..=span.end {
+            *labels.get_mut(span.row, col).unwrap() = Some(idx);
+        }
+    }
+
+    let mut roots: Vec<usize> = (0..nodes.len()).collect();
+    for (idx, span) in nodes.iter().enumerate() {
+        for col in span.start..=span.end {
+            for (r, c) in grid.neighbourhood(span.row, col, adjacency.radius, adjacency.metric) {
+                if let Some(other) = labels.get(r, c).unwrap().filter(|other| (idx < numbers) != (*other < numbers)) {
+                    let (a, b) = (find_root(&mut roots, idx), find_root(&mut roots, other));
+                    roots[a.max(b)] = a.min(b);
+                }
+            }
+        }
+    }
+
+    let mut clusters: Vec<Cluster> = Vec::new();
+    let mut cluster_idx: HashMap<usize, usize> = HashMap::new();
+    for (idx, span) in nodes.iter().enumerate() {
+        let root = find_root(&mut roots, idx);
+        let ci = *cluster_idx.entry(root).or_insert_with(|| {
+            clusters.push(Cluster{parts: Vec::new(), symbols: Vec::new(),
+                top_left: (span.row, span.start), bottom_right: (span.row, span.end)});
+            clusters.len() - 1
+        });
+        let cluster = &mut clusters[ci];
+        let c = *grid.get(span.row, span.start).unwrap();
+        if c.is_ascii_digit() {
+            cluster.parts.push(Part{row: span.row, col: span.start, value: span_value(grid, span)});
+        } else {
+            cluster.symbols.push((span.row, span.start, c));
+        }
+        cluster.top_left = (cluster.top_left.0.min(span.row), cluster.top_left.1.min(span.start));
+        cluster.bottom_right = (cluster.bottom_right.0.max(span.row), cluster.bottom_right.1.max(span.end));
+    }
+    // reading order of each cluster's first cell
+    clusters.sort_by_key(|c| {
+        let first_part = c.parts.first().map(|p| (p.row, p.col));
+        let first_symbol = c.symbols.first().map(|s| (s.0, s.1));
+        first_part.into_iter().chain(first_symbol).min()
+    });
+    clusters
+}
+
+fn process_lines_clusters<S: SelectSymbol>(lines: impl std::iter::Iterator<Item = String>, selector: &S, adjacency: Adjacency) -> Result<Vec<Cluster>, AoCError> {
+    let grid = load_schematic(lines)?;
+    Ok(find_clusters(&grid, selector, adjacency))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Highlight {
+    Ansi,
+    Plain
+}
+
+impl Highlight {
+    fn detect() -> Self {
+        if std::io::stdout().is_terminal() { Highlight::Ansi } else { Highlight::Plain }
+    }
+
+    // plain output marks parts as [n], excluded numbers as (n) and gears and their parts as {n}
+    fn wrap(&self, mark: Mark, text: &str) -> String {
+        match (self, mark) {
+            (Highlight::Ansi, Mark::Part) => format!("\x1b[32m{}\x1b[0m", text),
+            (Highlight::Ansi, Mark::Excluded) => format!("\x1b[31m{}\x1b[0m", text),
+            (Highlight::Ansi, Mark::Gear) => format!("\x1b[1;33m{}\x1b[0m", text),
+            (Highlight::Plain, Mark::Part) => format!("[{}]", text),
+            (Highlight::Plain, Mark::Excluded) => format!("({})", text),
+            (Highlight::Plain, Mark::Gear) => format!("{{{}}}", text)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mark {
+    Part,
+    Excluded,
+    Gear
+}
+
+// re-prints the schematic showing which numbers were counted as parts and which symbols formed gears
+fn explain_lines_day3<S: SelectSymbol + Clone>(lines: impl std::iter::Iterator<Item = String>, rule: &GearRule<S>, style: Highlight) -> Result<String, AoCError> {
+    let lines: Vec<String> = lines.collect();
+
+    let mut parts: HashSet<(usize, usize)> = HashSet::new();
+    let mut gear_marks: HashSet<(usize, usize)> = HashSet::new();
+    let mut part_sec = Section::with_adjacency(AnySymbol, rule.adjacency);
+    let mut gear_sec = Section::with_adjacency(rule.hub.clone(), rule.adjacency);
+    let flush = std::iter::repeat_n("", part_sec.lookahead());
+    for line in lines.iter().map(|l| l.as_str()).chain(flush) {
+        part_sec.push(line)?;
+        parts.extend(part_sec.find_adjacent_parts().iter().map(|p| (p.row, p.col)));
+        gear_sec.push(line)?;
+        for gear in gear_sec.find_gears(rule.count) {
+            gear_marks.insert((gear.row, gear.col));
+            gear_marks.extend(gear.parts.iter().map(|p| (p.row, p.col)));
+        }
+    }
+
+    let grid = load_schematic(&lines)?;
+    let mut ret = String::new();
+    for row in 0..grid.height() {
+        let nums = grid.row_runs(row, is_digit);
+        let mut col = 0;
+        while col < grid.width() {
+            match nums.iter().find(|s| s.start == col) {
+                Some(span) => {
+                    let text: String = grid.span_cells(span).iter().collect();
+                    let mark = if gear_marks.contains(&(row, col)) {
+                        Mark::Gear
+                    } else if parts.contains(&(row, col)) {
+                        Mark::Part
+                    } else {
+                        Mark::Excluded
+                    };
+                    ret.push_str(&style.wrap(mark, &text));
+                    col = span.end + 1;
+                },
+                None => {
+                    let c = grid.get(row, col).unwrap().to_string();
+                    if gear_marks.contains(&(row, col)) {
+                        ret.push_str(&style.wrap(Mark::Gear, &c));
+                    } else {
+                        ret.push_str(&c);
+                    }
+                    col += 1;
+                }
+            }
+        }
+        ret.push('\n');
+    }
+    Ok(ret)
+}
+
+pub fn explain_day3() {
+    if let Ok(lines) = common::read_lines("./data/day3input.txt") {
+        let lines_iter = lines.map(|l| l.unwrap());
+        match explain_lines_day3(lines_iter, &GearRule::default(), Highlight::detect()) {
+            Ok(out) => print!("{}", out),
+            Err(e) => println!("failed to explain schematic: {}", e)
+        }
+    }
+}
+
+fn process_lines_day3_part2(lines : impl std::iter::Iterator<Item = String>) -> Result<u64, AoCError> {
+    process_lines_gears(lines, &GearRule::default())
+}
+
+fn process_lines_gears<S: SelectSymbol + Clone>(lines : impl std::iter::Iterator<Item = String>, rule: &GearRule<S>) -> Result<u64, AoCError> {
+    let mut total: u64 = 0;
+    let mut sec = Section::with_adjacency(rule.hub.clone(), rule.adjacency);
+    let mut add_gears = |sec: &Section<S>| -> Result<(), AoCError> {
+        for gear in sec.find_gears(rule.count) {
+            total = total.checked_add(gear.value(rule.combine)?)
+                .ok_or(AoCError::InputFormatError("sum of gear values overflows u64"))?;
+        }
+        Ok(())
+    };
     for line in lines {
-        sec = sec.push(line.as_str());
-        let ret = sec.find_gears();
-        let sum = ret.iter().fold(0, |acc, nums| acc + nums.0 * nums.1);
-        total += sum;
+        sec.push(line.as_str())?;
+        add_gears(&sec)?;
+    }
+    for _ in 0..sec.lookahead() {
+        sec.push("")?;
+        add_gears(&sec)?;
     }
-    sec = sec.push("");
-    let ret = sec.find_gears();
-    let sum = ret.iter().fold(0, |acc, nums| acc + nums.0 * nums.1);
-    total += sum;
-    total
+    Ok(total)
 }
 
 #[cfg(test)]
 mod tests {
     use std::cmp::Ordering;
-    use crate::day3::{is_symbol, LineProc, process_lines_day3, process_lines_day3_part2, Section};
+    use crate::common;
+    use crate::common::{AoCError, Grid, Metric};
+    use crate::day3::{Adjacency, AnySymbol, CharClass, Cluster, Combine, explain_lines_day3, Gear, GearRule, Highlight, is_star, is_symbol, Part, PartCount, process_lines_day3, process_lines_day3_part2, process_lines_clusters, process_lines_gears, process_lines_parts, SelectSymbol, Section, SymbolSet};
+
+    // the puzzle's example schematic
+    const EXAMPLE: [&str; 10] = [
+        "467..114..",
+        "...*......",
+        "..35..633.",
+        "......#...",
+        "617*......",
+        ".....+.58.",
+        "..592.....",
+        "......755.",
+        "...$.*....",
+        ".664.598.."];
+
+    fn example() -> impl Iterator<Item = String> {
+        EXAMPLE.iter().map(|s| s.to_string())
+    }
 
     #[test]
     fn test_edge() {
@@ -210,7 +632,7 @@ mod tests {
             "......354..........*...............$........849.*.................................*.....242....469.&764.........................959*128.$..."];
 
         let test_iter = test.iter().map(|s| s.to_string()).into_iter();
-        let total = process_lines_day3(test_iter);
+        let total = process_lines_day3(test_iter).unwrap();
 
         assert_eq!(9626, total);
     }
@@ -230,7 +652,7 @@ mod tests {
             ".664.598.."];
 
         let ex_iter = ex.iter().map(|s| s.to_string()).into_iter();
-        let total = process_lines_day3(ex_iter);
+        let total = process_lines_day3(ex_iter).unwrap();
         assert_eq!(4361, total);
     }
 
@@ -249,10 +671,196 @@ mod tests {
             ".664.598.."];
 
         let ex_iter = ex.iter().map(|s| s.to_string()).into_iter();
-        let total = process_lines_day3_part2(ex_iter);
+        let total = process_lines_day3_part2(ex_iter).unwrap();
         assert_eq!(467835, total);
     }
 
+    #[test]
+    fn test_duplicate_gear_parts() {
+        let ex = [
+            "......",
+            ".12*12",
+            "......"];
+        let ex_iter = ex.iter().map(|s| s.to_string());
+        assert_eq!(144, process_lines_day3_part2(ex_iter).unwrap());
+
+        let mut sec = Section::new(is_star);
+        sec.push(".12...").unwrap();
+        sec.push("...*..").unwrap();
+        sec.push("..12..").unwrap();
+        assert_eq!(vec![Gear{row: 1, col: 3, parts: vec![
+            Part{row: 0, col: 1, value: 12},
+            Part{row: 2, col: 2, value: 12}]}], sec.find_gears(PartCount::Exactly(2)));
+    }
+
+    #[test]
+    fn test_gear_rules() {
+        let sum_rule = GearRule{combine: Combine::Sum, ..GearRule::default()};
+        assert_eq!(467 + 35 + 755 + 598, process_lines_gears(example(), &sum_rule).unwrap());
+
+        // the lone-part star next to 617 counts once a single part is enough
+        let max_rule = GearRule{count: PartCount::AtLeast(1), combine: Combine::Max, ..GearRule::default()};
+        assert_eq!(467 + 617 + 755, process_lines_gears(example(), &max_rule).unwrap());
+
+        let hash_rule = GearRule{hub: |c| c == '#', count: PartCount::Exactly(1), combine: Combine::Product, adjacency: Adjacency::default()};
+        assert_eq!(633, process_lines_gears(example(), &hash_rule).unwrap());
+
+        let none_rule = GearRule{count: PartCount::Exactly(3), ..GearRule::default()};
+        assert_eq!(0, process_lines_gears(example(), &none_rule).unwrap());
+
+        // a hub with no parts is worth nothing, even as an empty product
+        let lone = || ["...", ".*.", "..."].iter().map(|s| s.to_string());
+        let empty_rule = GearRule{count: PartCount::Exactly(0), ..GearRule::default()};
+        assert_eq!(0, process_lines_gears(lone(), &empty_rule).unwrap());
+
+        // six 4 digit parts around one hub don't fit a u64 product
+        let crowded = || ["9999.9999", "9999*9999", "9999.9999"].iter().map(|s| s.to_string());
+        let crowded_rule = GearRule{count: PartCount::AtLeast(2), ..GearRule::default()};
+        let res = process_lines_gears(crowded(), &crowded_rule);
+        assert!(matches!(res, Err(AoCError::InputValueError(msg)) if msg == "gear at row 2 column 5 overflows u64"));
+        let sum_rule = GearRule{count: PartCount::AtLeast(2), combine: Combine::Sum, ..GearRule::default()};
+        assert_eq!(6 * 9999, process_lines_gears(crowded(), &sum_rule).unwrap());
+    }
+
+    #[test]
+    fn test_explain() {
+        let plain = explain_lines_day3(example(), &GearRule::default(), Highlight::Plain).unwrap();
+        let expect = vec![
+            "{467}..(114)..",
+            "...{*}......",
+            "..{35}..[633].",
+            "......#...",
+            "[617]*......",
+            ".....+.(58).",
+            "..[592].....",
+            "......{755}.",
+            "...$.{*}....",
+            ".[664].{598}.."];
+        assert_eq!(expect, plain.lines().collect::<Vec<_>>());
+
+        let ansi = explain_lines_day3(example(), &GearRule::default(), Highlight::Ansi).unwrap();
+        assert!(ansi.starts_with("\x1b[1;33m467\x1b[0m..\x1b[31m114\x1b[0m.."));
+    }
+
+    #[test]
+    fn test_symbol_selectors() {
+        let not_digit_or_dot = CharClass::parse("[^.0-9]").unwrap();
+        for c in ['*', '#', '$', 'a'] {
+            assert_eq!(AnySymbol.select(c), not_digit_or_dot.select(c));
+        }
+        assert!(!not_digit_or_dot.select('.') && !not_digit_or_dot.select('7'));
+
+        let escaped = CharClass::parse(r"[\]\-a-c\d]").unwrap();
+        assert!(escaped.select(']') && escaped.select('-') && escaped.select('b') && escaped.select('5'));
+        assert!(!escaped.select('d'));
+        assert!(CharClass::parse("[-+]").unwrap().select('-'));
+        for bad in ["*", "[]", "[^]", "[z-a]", r"[\q]", "[a]b]"] {
+            assert!(CharClass::parse(bad).is_err(), "{}", bad);
+        }
+        assert!(SymbolSet::new("#$").select('$'));
+    }
+
+    #[test]
+    fn test_custom_selectors() {
+        assert_eq!(4361, process_lines_parts(example(), CharClass::parse("[^.\\d]").unwrap(), Adjacency::default()).unwrap());
+        assert_eq!(633 + 592, process_lines_parts(example(), SymbolSet::new("#+"), Adjacency::default()).unwrap());
+
+        let excluded = ['*', '$'];
+        let selector = |c: char| AnySymbol.select(c) && !excluded.contains(&c);
+        assert_eq!(633 + 592, process_lines_parts(example(), selector, Adjacency::default()).unwrap());
+    }
+
+    #[test]
+    fn test_adjacency() {
+        let ex = [
+            "1.......2.",
+            "..........",
+            "..#.....3.",
+            "..........",
+            "....4....5",
+            "..........",
+            "..6...7..."];
+        let ex_iter = || ex.iter().map(|s| s.to_string());
+        let within = |radius, metric| process_lines_parts(ex_iter(), AnySymbol, Adjacency{radius, metric}).unwrap();
+
+        assert_eq!(0, within(1, Metric::Chebyshev));
+        assert_eq!(1 + 4, within(2, Metric::Chebyshev));
+        assert_eq!(1 + 4 + 6 + 7, within(4, Metric::Chebyshev));
+        assert_eq!(1 + 2 + 3 + 4 + 6 + 7, within(6, Metric::Chebyshev));
+        assert_eq!(0, within(3, Metric::Manhattan));
+        assert_eq!(1 + 4 + 6, within(4, Metric::Manhattan));
+        assert_eq!(1 + 3 + 4 + 6, within(6, Metric::Manhattan));
+        assert_eq!(6, within(4, Metric::Orthogonal));
+        assert_eq!(3 + 6, within(6, Metric::Orthogonal));
+
+        let hub = GearRule{count: PartCount::AtLeast(1), combine: Combine::Sum, hub: SymbolSet::new("#"),
+            adjacency: Adjacency{radius: 6, metric: Metric::Chebyshev}};
+        assert_eq!(1 + 2 + 3 + 4 + 6 + 7, process_lines_gears(ex_iter(), &hub).unwrap());
+    }
+
+    #[test]
+    fn test_adjacency_matches_whole_grid() {
+        let lines: Vec<String> = common::read_lines("./data/day3input.txt").unwrap().take(40).map(|l| l.unwrap()).collect();
+        let grid = Grid::from_lines(&lines, |c| c).unwrap();
+        for metric in [Metric::Chebyshev, Metric::Manhattan, Metric::Orthogonal] {
+            for radius in 0..4 {
+                let expect: u32 = grid.runs(|c| c.is_ascii_digit()).iter().filter(|span| {
+                    (span.start..=span.end).any(|col| grid.neighbourhood(span.row, col, radius, metric)
+                        .any(|(r, c)| is_symbol(*grid.get(r, c).unwrap())))
+                }).map(|span| grid.span_cells(span).iter().collect::<String>().parse::<u32>().unwrap()).sum();
+                let check = process_lines_parts(lines.iter().cloned(), AnySymbol, Adjacency{radius, metric}).unwrap();
+                assert_eq!(expect, check, "{:?} radius {}", metric, radius);
+            }
+        }
+    }
+
+    #[test]
+    fn test_clusters() {
+        let clusters = process_lines_clusters(example(), &AnySymbol, Adjacency::default()).unwrap();
+        assert_eq!(8, clusters.len());
+
+        assert_eq!(Cluster{
+            parts: vec![Part{row: 0, col: 0, value: 467}, Part{row: 2, col: 2, value: 35}],
+            symbols: vec![(1, 3, '*')],
+            top_left: (0, 0),
+            bottom_right: (2, 3)}, clusters[0]);
+        assert_eq!(502, clusters[0].sum());
+        assert!(clusters[1].symbols.is_empty() && clusters[1].parts[0].value == 114);
+
+        let gear = clusters.iter().find(|c| c.symbols == vec![(8, 5, '*')]).unwrap();
+        assert_eq!(vec![755, 598], gear.parts.iter().map(|p| p.value).collect::<Vec<_>>());
+        assert_eq!(((7, 5), (9, 8)), (gear.top_left, gear.bottom_right));
+
+        let total: u64 = clusters.iter().filter(|c| !c.symbols.is_empty()).map(|c| c.sum()).sum();
+        assert_eq!(4361, total);
+
+        let isolated: Vec<u32> = clusters.iter().filter(|c| c.symbols.is_empty()).flat_map(|c| c.parts.iter().map(|p| p.value)).collect();
+        assert_eq!(vec![114, 58], isolated);
+
+        // stacked numbers only share a cluster through a symbol
+        let stacked = process_lines_clusters(["12.", "34.", "..*"].iter().map(|s| s.to_string()), &AnySymbol, Adjacency::default()).unwrap();
+        assert_eq!(2, stacked.len());
+        assert_eq!((vec![Part{row: 0, col: 0, value: 12}], vec![]), (stacked[0].parts.clone(), stacked[0].symbols.clone()));
+        assert_eq!((vec![Part{row: 1, col: 0, value: 34}], vec![(2, 2, '*')]), (stacked[1].parts.clone(), stacked[1].symbols.clone()));
+        let total: u64 = stacked.iter().filter(|c| !c.symbols.is_empty()).map(|c| c.sum()).sum();
+        assert_eq!(34, total);
+    }
+
+    #[test]
+    fn test_invalid_schematic() {
+        let ragged = ["467..", "...*..", "..35."];
+        let err = process_lines_day3(ragged.iter().map(|s| s.to_string()));
+        assert!(matches!(err, Err(AoCError::InputValueError(msg)) if msg == "schematic line 2 has width 6, expected 5"));
+
+        let non_ascii = ["467..", "..é*.", "..35."];
+        let err = process_lines_day3(non_ascii.iter().map(|s| s.to_string()));
+        assert!(matches!(err, Err(AoCError::InputValueError(msg)) if msg == "schematic line 2 has unsupported character at column 3"));
+
+        let gap = ["467..", "", "..35."];
+        assert!(process_lines_day3_part2(gap.iter().map(|s| s.to_string())).is_err());
+        assert!(explain_lines_day3(gap.iter().map(|s| s.to_string()), &GearRule::default(), Highlight::Plain).is_err());
+    }
+
     #[test]
     fn test_day3() {
         let line1 = "....411...............838......721.....44..............................................607..................................................";
@@ -260,40 +868,39 @@ mod tests {
         let line3 = ".....*......*...892.........971...%....131....*..........*.......515...$.......157.....412.............-.....*.............*............594.";
         let line4 = "..856.495....13...-...............602..........36...$.985....341*.........88.....*.921....................122..................806..508.....";
 
-        let mut sec = Section{
-            select_func: is_symbol,
-            width: 0,
-            preceding: LineProc::default(),
-            current: LineProc::default(),
-            next: LineProc::default(),
-        };
+        let mut sec = Section::new(is_symbol);
 
-        sec = sec.push(line1);
-        sec = sec.push(line2);
-        assert_eq!(0, sec.preceding.nums.len());
+        sec.push(line1).unwrap();
+        sec.push(line2).unwrap();
+        assert_eq!(Some(0), sec.current());
+        assert_eq!(0, sec.first_row);
 
         let expect: Vec<u32> = vec![411, 721,607];
         let check = sec.find_adjacent_nums();
         assert_eq!(expect, check);
 
-        sec = sec.push(line3);
-        assert_ne!(0, sec.preceding.nums.len());
+        sec.push(line3).unwrap();
+        assert_eq!(3, sec.window.height());
 
         let expect1: Vec<u32> = vec![519, 97, 994, 404, 43, 440, 882, 673, 505];
         let check1 = sec.find_adjacent_nums();
         assert_eq!(expect1, check1);
 
-        sec = sec.push(line4);
+        sec.push(line4).unwrap();
+        assert_eq!(3, sec.window.height());
+        assert_eq!(1, sec.first_row);
 
         let expect2: Vec<u32> = vec![892, 971, 131, 515, 157, 412];
         let check2 = sec.find_adjacent_nums();
         assert_eq!(expect2, check2);
 
-        assert_eq!(7, sec.current.nums.len());
-        assert_eq!(9, sec.current.symbol_positions.len());
+        assert_eq!(7, sec.current_nums().len());
+        let cur = sec.current_window_row().unwrap();
+        assert_eq!(9, sec.window.row(cur).unwrap().iter().filter(|c| is_symbol(**c)).count());
 
-        sec = sec.push("");
-        assert_eq!(0, sec.next.nums.len());
+        sec.push("").unwrap();
+        assert_eq!(Some(3), sec.current());
+        assert_eq!(2, sec.window.height());
     }
 
     #[test]
@@ -1,12 +1,14 @@
 use std::cmp::min;
 use std::collections::HashSet;
+use std::str::FromStr;
 use nom::bytes::complete::tag;
-use nom::character::complete::{multispace1, space1};
+use nom::character::complete::{multispace1, space0, space1};
 use nom::IResult;
 use nom::multi::separated_list1;
 use nom::sequence::tuple;
+use num_bigint::BigUint;
 use crate::common;
-use crate::common::number;
+use crate::common::{AoCError, number};
 
 fn card_pre_parser(input: &str) -> IResult<&str, u32> {
     tuple((tag("Card"), space1, crate::common::number, tag(":")))(input).map(|(remaining, res)| {
@@ -14,29 +16,231 @@ fn card_pre_parser(input: &str) -> IResult<&str, u32> {
     })
 }
 
-fn process_line_day4(line: &str) -> usize {
-    let mut parser = tuple((
-        card_pre_parser,
-        multispace1,
-        separated_list1(multispace1, number::<u32>),
-        multispace1,
-        tag("|"),
-        multispace1,
-        separated_list1(multispace1, number::<u32>))
-    );
-    let (_, wining_nums, card_nums) = parser(line)
-        .map(|res| (res.1.0, res.1.2, res.1.6)).unwrap_or_default();
-    let winning_set: HashSet<_> = wining_nums.iter().collect();
-    let card_set: HashSet<_> = card_nums.iter().collect();
-    let sect: HashSet<_> = winning_set.intersection(&card_set).collect();
-    sect.len()
-}
-
-pub fn do_day4() {
+#[derive(Debug, PartialEq)]
+struct Scratchcard {
+    id: u32,
+    winning: Vec<u32>,
+    have: Vec<u32>
+}
+
+// default bitset size for matches(): numbers below 128 are matched with bitsets, anything
+// larger uses hash sets. callers expecting other ranges can pick their own with matches_within
+const BITSET_WORDS: usize = 2;
+
+// None if any number doesn't fit in a WORDS * 64 bit set
+fn to_bitset<const WORDS: usize>(nums: &[u32]) -> Option<[u64; WORDS]> {
+    let mut ret = [0u64; WORDS];
+    for n in nums {
+        let word = ret.get_mut(*n as usize / 64)?;
+        *word |= 1 << (n % 64);
+    }
+    Some(ret)
+}
+
+impl Scratchcard {
+    fn matches(&self) -> usize {
+        self.matches_within::<BITSET_WORDS>()
+    }
+
+    // bitsets of WORDS * 64 bits when every number fits, hash sets otherwise
+    fn matches_within<const WORDS: usize>(&self) -> usize {
+        self.matches_bitset::<WORDS>().unwrap_or_else(|| self.matches_hashed())
+    }
+
+    fn matches_bitset<const WORDS: usize>(&self) -> Option<usize> {
+        let winning = to_bitset::<WORDS>(&self.winning)?;
+        let have = to_bitset::<WORDS>(&self.have)?;
+        Some(winning.iter().zip(have.iter()).map(|(w, h)| (w & h).count_ones() as usize).sum())
+    }
+
+    fn matches_hashed(&self) -> usize {
+        let winning_set: HashSet<_> = self.winning.iter().collect();
+        let card_set: HashSet<_> = self.have.iter().collect();
+        let sect: HashSet<_> = winning_set.intersection(&card_set).collect();
+        sect.len()
+    }
+}
+
+impl FromStr for Scratchcard {
+    type Err = AoCError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = tuple((
+            card_pre_parser,
+            multispace1,
+            separated_list1(multispace1, number::<u32>),
+            multispace1,
+            tag("|"),
+            multispace1,
+            separated_list1(multispace1, number::<u32>),
+            space0)
+        );
+        match parser(s) {
+            Ok(("", (id, _, winning, _, _, _, have, _))) => Ok(Scratchcard{id, winning, have}),
+            _ => Err(AoCError::InputValueError(format!("invalid scratchcard: {}", s)))
+        }
+    }
+}
+
+fn process_line_day4(line: &str) -> Result<usize, AoCError> {
+    Ok(Scratchcard::from_str(line)?.matches())
+}
+
+// part 2 copies cards by position, so ids must run 1, 2, 3 ... in input order
+fn process_day4_input(lines : impl Iterator<Item = String>) -> Result<Vec<Scratchcard>, AoCError> {
+    let cards = lines.map(|l| Scratchcard::from_str(l.as_str())).collect::<Result<Vec<_>, _>>()?;
+    for (idx, card) in cards.iter().enumerate() {
+        if card.id as usize != idx + 1 {
+            return Err(AoCError::InputValueError(format!("expected card {} on line {}, found card {}", idx + 1, idx + 1, card.id)))
+        }
+    }
+    Ok(cards)
+}
+
+// part 1 scoring of a card from its match count. scores are BigUint since the doubling rule
+// passes u64 after 64 matches; no matches always scores zero
+pub trait Scoring {
+    fn score(&self, matches: usize) -> BigUint;
+}
+
+// 1, 2, 4, 8 ... - the puzzle rule
+struct Doubling;
+
+struct Linear;
+
+// 1, 1, 2, 3, 5 ...
+struct Fibonacci;
+
+// scores for 1, 2, 3 ... matches; counts past the end of the table score the last entry
+struct Table(Vec<u64>);
+
+impl Scoring for Doubling {
+    fn score(&self, matches: usize) -> BigUint {
+        if matches == 0 {BigUint::from(0u32)} else {BigUint::from(1u32) << (matches - 1)}
+    }
+}
+
+impl Scoring for Linear {
+    fn score(&self, matches: usize) -> BigUint {
+        BigUint::from(matches)
+    }
+}
+
+impl Scoring for Fibonacci {
+    fn score(&self, matches: usize) -> BigUint {
+        let (mut prev, mut cur) = (BigUint::from(1u32), BigUint::from(0u32));
+        for _ in 0..matches {
+            let next = &prev + &cur;
+            prev = cur;
+            cur = next;
+        }
+        cur
+    }
+}
+
+impl Scoring for Table {
+    fn score(&self, matches: usize) -> BigUint {
+        match matches {
+            0 => BigUint::from(0u32),
+            m => BigUint::from(*self.0.get(m - 1).or(self.0.last()).unwrap_or(&0))
+        }
+    }
+}
+
+// accepts "doubling", "linear", "fibonacci" or "table:" followed by comma separated scores
+pub fn parse_scoring(rule: &str) -> Result<Box<dyn Scoring>, AoCError> {
+    match rule {
+        "doubling" => Ok(Box::new(Doubling)),
+        "linear" => Ok(Box::new(Linear)),
+        "fibonacci" => Ok(Box::new(Fibonacci)),
+        _ => match rule.strip_prefix("table:") {
+            Some(table) => {
+                let scores = table.split(',').map(|v| v.trim().parse::<u64>()).collect::<Result<Vec<_>, _>>()?;
+                Ok(Box::new(Table(scores)))
+            },
+            None => Err(AoCError::InputValueError(format!("unknown scoring rule: {}", rule)))
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct CardTrace {
+    id: u32,
+    matches: usize,
+    points: BigUint,
+    copies: BigUint,
+    contributors: Vec<(u32, BigUint)> // earlier card id and the copies it added to this one
+}
+
+fn trace_part2(cards: &[Scratchcard], scoring: &dyn Scoring) -> Vec<CardTrace> {
+    let mut trace: Vec<CardTrace> = cards.iter().map(|c| {
+        let matches = c.matches();
+        CardTrace{id: c.id, matches, points: scoring.score(matches), copies: BigUint::from(1u32), contributors: Vec::new()}
+    }).collect();
+    for idx in 0..trace.len() {
+        let end = min(trace.len(), (idx+1).saturating_add(trace[idx].matches));
+        let (id, copies) = (trace[idx].id, trace[idx].copies.clone());
+        for t in trace[idx+1..end].iter_mut() {
+            t.copies += &copies;
+            t.contributors.push((id, copies.clone()));
+        }
+    }
+    trace
+}
+
+fn render_trace(trace: &[CardTrace]) -> String {
+    let headers = ["card", "matches", "points", "copies", "copied from (card x copies)"];
+    let rows: Vec<[String; 5]> = trace.iter().map(|t| [
+        t.id.to_string(),
+        t.matches.to_string(),
+        t.points.to_string(),
+        t.copies.to_string(),
+        t.contributors.iter().map(|(id, cnt)| format!("{}x{}", id, cnt)).collect::<Vec<_>>().join(" ")
+    ]).collect();
+    let widths: Vec<usize> = (0..4).map(|col| {
+        rows.iter().map(|r| r[col].len()).chain([headers[col].len()]).max().unwrap()
+    }).collect();
+
+    let mut ret = String::new();
+    let mut push_row = |cells: [&str; 5]| {
+        let line = format!("{:>w0$} | {:>w1$} | {:>w2$} | {:>w3$} | {}",
+            cells[0], cells[1], cells[2], cells[3], cells[4],
+            w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3]);
+        ret.push_str(line.trim_end());
+        ret.push('\n');
+    };
+    push_row(headers);
+    for r in rows.iter() {
+        push_row([&r[0], &r[1], &r[2], &r[3], &r[4]]);
+    }
+    ret
+}
+
+pub fn explain_day4(scoring: &dyn Scoring) {
+    if let Ok(lines) = common::read_lines("./data/day4input.txt") {
+        match process_day4_input(lines.map(|l| l.unwrap())) {
+            Ok(cards) => print!("{}", render_trace(&trace_part2(&cards, scoring))),
+            Err(e) => println!("failed to read scratchcards: {}", e)
+        }
+    }
+}
+
+fn process_part1(results: &[usize], scoring: &dyn Scoring) -> BigUint {
+    results.iter().map(|x| scoring.score(*x)).sum()
+}
+
+pub fn do_day4(scoring: &dyn Scoring) {
     if let Ok(lines) = common::read_lines("./data/day4input.txt") {
-        let card_matches: Vec<usize> = lines.map(|l| process_line_day4(&l.unwrap())).collect();
+        let cards = match process_day4_input(lines.map(|l| l.unwrap())) {
+            Ok(cards) => cards,
+            Err(e) => {
+                println!("failed to read scratchcards: {}", e);
+                return
+            }
+        };
+        let card_matches: Vec<usize> = cards.iter().map(|c| c.matches()).collect();
 
-        let res_part1 = card_matches.iter().fold(0, |acc, x| acc + if *x == 0 {0} else {2u32.pow((x-1) as u32)});
+        let res_part1 = process_part1(&card_matches, scoring);
         println!("final sum, part1: {}", res_part1);
 
         let res_part2 = process_part2(card_matches);
@@ -44,20 +248,45 @@ pub fn do_day4() {
     }
 }
 
-fn process_part2(results: Vec<usize>) -> i32 {
-    let mut card_counts = vec![1 ;results.len()];
-    for idx in 0..results.len()-1 {
-        let end = min(results.len(), idx+1+results[idx]);
+// total cards after copying; counts grow exponentially with long cascades so the u64 pass
+// falls back to BigUint when it would overflow
+fn process_part2(results: Vec<usize>) -> BigUint {
+    match count_copies_u64(&results) {
+        Some(total) => BigUint::from(total),
+        None => count_copies_big(&results)
+    }
+}
+
+fn count_copies_u64(results: &[usize]) -> Option<u64> {
+    let mut card_counts = vec![1u64; results.len()];
+    for idx in 0..results.len() {
+        let end = min(results.len(), (idx+1).saturating_add(results[idx]));
+        for x in idx+1..end {
+            card_counts[x] = card_counts[x].checked_add(card_counts[idx])?;
+        }
+    }
+    card_counts.iter().try_fold(0u64, |acc, x| acc.checked_add(*x))
+}
+
+fn count_copies_big(results: &[usize]) -> BigUint {
+    let mut card_counts = vec![BigUint::from(1u32); results.len()];
+    for idx in 0..results.len() {
+        let end = min(results.len(), (idx+1).saturating_add(results[idx]));
         for x in idx+1..end {
-            card_counts[x] = card_counts[x] + card_counts[idx];
+            card_counts[x] = &card_counts[x] + &card_counts[idx];
         }
     }
-    card_counts.iter().fold(0, |acc, x| acc + x)
+    card_counts.iter().sum()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day4::{process_line_day4, process_part2};
+    use std::str::FromStr;
+    use std::time::Instant;
+    use num_bigint::BigUint;
+    use crate::common;
+    use crate::common::{AoCError, XorShift};
+    use crate::day4::{CardTrace, count_copies_u64, Doubling, Fibonacci, Linear, parse_scoring, process_part1, Scoring, Table, process_day4_input, process_line_day4, process_part2, render_trace, Scratchcard, trace_part2};
 
     #[test]
     fn test_example() {
@@ -69,16 +298,162 @@ mod tests {
         "Card 5: 87 83 26 28 32 | 88 30 70 12 93 22 82 36",
         "Card 6: 31 18 13 56 72 | 74 77 10 23 35 67 36 11"];
 
-        let mut total = 0;
         let mut results: Vec<usize> = Vec::new();
         for line in ex.iter() {
-            let res = process_line_day4(line);
-            total += if res == 0 {0} else {2u32.pow((res-1) as u32)};
+            let res = process_line_day4(line).unwrap();
             results.push(res)
         };
-        assert_eq!(13, total);
+        assert_eq!(BigUint::from(13u32), process_part1(&results, &Doubling));
 
         let total_cards = process_part2(results);
-        assert_eq!(30, total_cards);
+        assert_eq!(BigUint::from(30u32), total_cards);
+    }
+
+    #[test]
+    fn test_part2_limits() {
+        assert_eq!(BigUint::from(0u32), process_part2(vec![]));
+        assert_eq!(BigUint::from(1u32), process_part2(vec![5]));
+
+        // every card copies all the following ones, so card n ends up with 2^(n-1) copies
+        assert_eq!(BigUint::from(u64::MAX), process_part2(vec![usize::MAX; 64]));
+        assert_eq!((BigUint::from(1u32) << 70u32) - 1u32, process_part2(vec![70; 70]));
+        assert_eq!(None, count_copies_u64(&[70; 70]));
+    }
+
+    #[test]
+    fn test_trace() {
+        let ex = [
+        "Card 1: 41 48 83 86 17 | 83 86  6 31 17  9 48 53",
+        "Card 2: 13 32 20 16 61 | 61 30 68 82 17 32 24 19",
+        "Card 3:  1 21 53 59 44 | 69 82 63 72 16 21 14  1",
+        "Card 4: 41 92 73 84 69 | 59 84 76 51 58  5 54 83",
+        "Card 5: 87 83 26 28 32 | 88 30 70 12 93 22 82 36",
+        "Card 6: 31 18 13 56 72 | 74 77 10 23 35 67 36 11"];
+        let cards = process_day4_input(ex.iter().map(|s| s.to_string())).unwrap();
+        let trace = trace_part2(&cards, &Doubling);
+
+        assert_eq!(CardTrace{id: 4, matches: 1, points: BigUint::from(1u32), copies: BigUint::from(8u32), contributors: vec![
+            (1, BigUint::from(1u32)), (2, BigUint::from(2u32)), (3, BigUint::from(4u32))]}, trace[3]);
+        let total: BigUint = trace.iter().map(|t| &t.copies).sum();
+        assert_eq!(BigUint::from(30u32), total);
+        assert_eq!(BigUint::from(13u32), trace.iter().map(|t| &t.points).sum());
+
+        let expect = vec![
+            "card | matches | points | copies | copied from (card x copies)",
+            "   1 |       4 |      8 |      1 |",
+            "   2 |       2 |      2 |      2 | 1x1",
+            "   3 |       2 |      2 |      4 | 1x1 2x2",
+            "   4 |       1 |      1 |      8 | 1x1 2x2 3x4",
+            "   5 |       0 |      0 |     14 | 1x1 3x4 4x8",
+            "   6 |       0 |      0 |      1 |"];
+        assert_eq!(expect, render_trace(&trace).lines().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_scoring() {
+        let doubling: Vec<BigUint> = (0..5).map(|m| Doubling.score(m)).collect();
+        assert_eq!(vec![0u32, 1, 2, 4, 8].into_iter().map(BigUint::from).collect::<Vec<_>>(), doubling);
+        assert_eq!(BigUint::from(1u32) << 99u32, Doubling.score(100));
+        assert_eq!(BigUint::from(7u32), Linear.score(7));
+        let fib: Vec<BigUint> = (0..8).map(|m| Fibonacci.score(m)).collect();
+        assert_eq!(vec![0u32, 1, 1, 2, 3, 5, 8, 13].into_iter().map(BigUint::from).collect::<Vec<_>>(), fib);
+        let table = Table(vec![1, 5, 10]);
+        assert_eq!(vec![0u32, 1, 5, 10, 10].into_iter().map(BigUint::from).collect::<Vec<_>>(),
+            (0..5).map(|m| table.score(m)).collect::<Vec<_>>());
+
+        let matches = vec![4, 2, 2, 1, 0, 0];
+        assert_eq!(BigUint::from(13u32), process_part1(&matches, parse_scoring("doubling").unwrap().as_ref()));
+        assert_eq!(BigUint::from(9u32), process_part1(&matches, parse_scoring("linear").unwrap().as_ref()));
+        assert_eq!(BigUint::from(6u32), process_part1(&matches, parse_scoring("fibonacci").unwrap().as_ref()));
+        assert_eq!(BigUint::from(12u32), process_part1(&matches, parse_scoring("table:1, 3,5").unwrap().as_ref()));
+        assert!(parse_scoring("squares").is_err());
+        assert!(parse_scoring("table:1,x").is_err());
+    }
+
+    #[test]
+    fn test_bitset_matching() {
+        let card = Scratchcard{id: 1, winning: vec![0, 63, 64, 127, 5], have: vec![127, 64, 63, 0, 6, 6]};
+        assert_eq!(Some(4), card.matches_bitset::<2>());
+        assert_eq!(None, card.matches_bitset::<1>());
+        assert_eq!(4, card.matches_hashed());
+
+        let big = Scratchcard{id: 1, winning: vec![500, 7], have: vec![7, 500, 128]};
+        assert_eq!(None, big.matches_bitset::<2>());
+        assert_eq!(Some(2), big.matches_bitset::<8>());
+        assert_eq!(2, big.matches());
+    }
+
+    fn generate_cards(count: usize, bound: u64) -> Vec<Scratchcard> {
+        let mut rng = XorShift(0x2545F4914F6CDD1D);
+        let mut next = move || rng.below(bound) as u32;
+        (1..=count).map(|id| Scratchcard{
+            id: id as u32,
+            winning: (0..10).map(|_| next()).collect(),
+            have: (0..25).map(|_| next()).collect()
+        }).collect()
+    }
+
+    #[test]
+    fn test_bitset_matches_hashed() {
+        for card in generate_cards(1000, 100) {
+            assert_eq!(Some(card.matches_hashed()), card.matches_bitset::<2>());
+            assert_eq!(card.matches_hashed(), card.matches_within::<1>());
+        }
+    }
+
+    // cargo test --release bench_matching -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_matching() {
+        // numbers below 100 fit the default bound; below 1000 they need 16 words
+        for (bound, cards) in [(100, generate_cards(1_000_000, 100)), (1000, generate_cards(1_000_000, 1000))] {
+            let start = Instant::now();
+            let hashed: usize = cards.iter().map(|c| c.matches_hashed()).sum();
+            let hashed_time = start.elapsed();
+            let timed = |label: &str, matches: fn(&Scratchcard) -> usize| {
+                let start = Instant::now();
+                let bitset: usize = cards.iter().map(matches).sum();
+                let bitset_time = start.elapsed();
+                assert_eq!(hashed, bitset);
+                println!("numbers below {}, hashed: {:?}, {}: {:?} ({:.1}x)", bound, hashed_time, label, bitset_time,
+                    hashed_time.as_secs_f64() / bitset_time.as_secs_f64());
+            };
+            timed("default bitset", Scratchcard::matches);
+            timed("16 word bitset", Scratchcard::matches_within::<16>);
+        }
+    }
+
+    #[test]
+    fn test_scratchcard_parsing() {
+        let card = Scratchcard::from_str("Card  12: 41 48 | 83  6 41").unwrap();
+        assert_eq!(Scratchcard{id: 12, winning: vec![41, 48], have: vec![83, 6, 41]}, card);
+        assert_eq!(1, card.matches());
+
+        for bad in ["Card 1: 41 48 83", "Card 1 41 | 83", "Crd 1: 41 | 83", "Card 1: 41 | 83 x", "Card 1: | 83", ""] {
+            assert!(Scratchcard::from_str(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_card_ids() {
+        let ex = [
+            "Card 1: 41 48 | 83 86",
+            "Card 3: 13 32 | 61 30",
+            "Card 2:  1 21 | 69 82"];
+        let res = process_day4_input(ex.iter().map(|s| s.to_string()));
+        assert!(matches!(res, Err(AoCError::InputValueError(msg)) if msg == "expected card 2 on line 2, found card 3"));
+
+        let res = process_day4_input(ex[..1].iter().map(|s| s.to_string()));
+        assert_eq!(1, res.unwrap().len());
+    }
+
+    #[test]
+    fn test_day4() {
+        if let Ok(lines) = common::read_lines("./data/day4input.txt") {
+            let cards = process_day4_input(lines.map(|l| l.unwrap())).unwrap();
+            assert_eq!(cards.len() as u32, cards.last().unwrap().id);
+            let total_cards = process_part2(cards.iter().map(|c| c.matches()).collect());
+            assert_eq!(BigUint::from(9881048u32), total_cards);
+        }
     }
 }
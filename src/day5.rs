@@ -1,10 +1,296 @@
 use std::cmp::{min, Ordering};
 use std::error::Error;
-use nom::character::complete::multispace1;
-use nom::multi::separated_list1;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take_while1};
+use nom::character::complete::{alpha1, char, digit1, multispace0, multispace1, space0};
+use nom::combinator::{map, map_res};
+use nom::IResult;
+use nom::multi::{separated_list0, separated_list1};
+use nom::sequence::{delimited, preceded, separated_pair, tuple};
+use crate::common;
 use crate::common::{AoCError, number};
 
-fn process_day5_input(lines : impl Iterator<Item = String>) -> Result<(Vec<u64>, Vec<Vec<RangeMap>>), AoCError> {
+// one "<from>-to-<to> map:" group
+#[derive(Debug)]
+struct Layer {
+    from: String,
+    to: String,
+    maps: Vec<RangeMap>
+}
+
+fn map_header_parser(input: &str) -> IResult<&str, (&str, &str)> {
+    tuple((alpha1, tag("-to-"), alpha1, tag(" map:"), space0))(input).map(|(remaining, res)| {
+        (remaining, (res.0, res.2))
+    })
+}
+
+// maps keyed by their source and destination categories, so groups can come in any order
+#[derive(Debug)]
+struct Almanac {
+    seeds: Vec<u64>,
+    layers: Vec<Layer>,
+    warnings: Vec<String>
+}
+
+impl Almanac {
+    fn new(seeds: Vec<u64>, layers: Vec<Layer>) -> Result<Almanac, AoCError> {
+        for (idx, l) in layers.iter().enumerate() {
+            if layers[..idx].iter().any(|o| o.from == l.from && o.to == l.to) {
+                return Err(AoCError::InputValueError(format!("duplicate {}-to-{} map", l.from, l.to)))
+            }
+        }
+        let mut ret = Almanac{seeds, layers, warnings: Vec::new()};
+        if let Some(cycle) = ret.find_cycle() {
+            return Err(AoCError::InputValueError(format!("category maps form a cycle: {}", cycle.join(" -> "))))
+        }
+        ret.warnings = ret.validate()?;
+        Ok(ret)
+    }
+
+    // fails on maps that would overflow u64 or whose sources overlap - the binary search in
+    // find_by_source would pick an arbitrary match. returns the suspicious but usable things found
+    fn validate(&self) -> Result<Vec<String>, AoCError> {
+        let mut warnings: Vec<String> = Vec::new();
+        for l in self.layers.iter() {
+            let name = l.name();
+            for rm in l.maps.iter() {
+                if rm.src.checked_add(rm.width).is_none() || rm.dest.checked_add(rm.width).is_none() {
+                    return Err(AoCError::InputValueError(format!(
+                        "{}: map {} {} {} overflows u64", name, rm.dest, rm.src, rm.width)))
+                }
+                if rm.width == 0 {
+                    warnings.push(format!("{}: map {} {} 0 has zero width", name, rm.dest, rm.src));
+                } else if rm.src == rm.dest {
+                    warnings.push(format!("{}: map {} {} {} maps onto itself", name, rm.dest, rm.src, rm.width));
+                }
+            }
+            // maps are sorted by source, so only neighbours can overlap. zero width maps cover
+            // nothing, so they only get the warning above whatever their line order
+            let by_src: Vec<&RangeMap> = l.maps.iter().filter(|rm| rm.width > 0).collect();
+            for w in by_src.windows(2) {
+                if w[0].src_range().end() > w[1].src {
+                    return Err(AoCError::InputValueError(format!(
+                        "{}: source ranges {}..{} and {}..{} overlap", name,
+                        w[0].src, w[0].src_range().end(), w[1].src, w[1].src_range().end())))
+                }
+            }
+            let mut by_dest: Vec<&RangeMap> = l.maps.iter().filter(|rm| rm.width > 0).collect();
+            by_dest.sort_by_key(|rm| rm.dest);
+            for w in by_dest.windows(2) {
+                if w[0].dest_range().end() > w[1].dest {
+                    warnings.push(format!("{}: destination ranges {}..{} and {}..{} overlap", name,
+                        w[0].dest, w[0].dest_range().end(), w[1].dest, w[1].dest_range().end()));
+                }
+            }
+        }
+        Ok(warnings)
+    }
+
+    fn categories(&self) -> Vec<&str> {
+        let mut ret: Vec<&str> = self.layers.iter().flat_map(|l| [l.from.as_str(), l.to.as_str()]).collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
+    fn outgoing<'a>(&'a self, category: &'a str) -> impl Iterator<Item = &'a Layer> + 'a {
+        self.layers.iter().filter(move |l| l.from == category)
+    }
+
+    fn find_cycle(&self) -> Option<Vec<String>> {
+        // depth first search keeping the current path; reaching a category on the path is a cycle
+        fn visit<'a>(almanac: &'a Almanac, category: &'a str, path: &mut Vec<&'a str>, done: &mut Vec<&'a str>) -> Option<Vec<String>> {
+            if let Some(pos) = path.iter().position(|c| *c == category) {
+                let mut cycle: Vec<String> = path[pos..].iter().map(|c| c.to_string()).collect();
+                cycle.push(category.to_string());
+                return Some(cycle)
+            }
+            if done.contains(&category) {
+                return None
+            }
+            path.push(category);
+            for l in almanac.outgoing(category) {
+                if let Some(cycle) = visit(almanac, l.to.as_str(), path, done) {
+                    return Some(cycle)
+                }
+            }
+            path.pop();
+            done.push(category);
+            None
+        }
+        let mut done: Vec<&str> = Vec::new();
+        self.categories().into_iter().find_map(|c| visit(self, c, &mut Vec::new(), &mut done))
+    }
+
+    // the layers to apply, in order, to map a value from one category to another
+    fn chain(&self, from: &str, to: &str) -> Result<Vec<&Vec<RangeMap>>, AoCError> {
+        // breadth first, remembering which layer reached each category
+        let mut reached: Vec<(&str, Option<usize>)> = vec![(from, None)];
+        let mut idx = 0;
+        while idx < reached.len() && reached[idx].0 != to {
+            let category = reached[idx].0;
+            for (li, l) in self.layers.iter().enumerate().filter(|(_, l)| l.from == category) {
+                if !reached.iter().any(|(c, _)| *c == l.to) {
+                    reached.push((l.to.as_str(), Some(li)));
+                }
+            }
+            idx += 1;
+        }
+        if idx == reached.len() {
+            return Err(AoCError::InputValueError(format!("no chain of maps from {} to {}", from, to)))
+        }
+
+        let mut ret: Vec<&Vec<RangeMap>> = Vec::new();
+        let mut category = to;
+        while let Some((_, Some(li))) = reached.iter().find(|(c, _)| *c == category) {
+            ret.push(&self.layers[*li].maps);
+            category = self.layers[*li].from.as_str();
+        }
+        ret.reverse();
+        Ok(ret)
+    }
+
+    fn chain_owned(&self, from: &str, to: &str) -> Result<Vec<Vec<RangeMap>>, AoCError> {
+        Ok(self.chain(from, to)?.into_iter().cloned().collect())
+    }
+}
+
+impl Layer {
+    fn name(&self) -> String {
+        format!("{}-to-{} map", self.from, self.to)
+    }
+
+    fn coverage(&self) -> LayerCoverage {
+        let covered = RangeSet::from_ranges(self.maps.iter().map(|rm| rm.src_range()));
+        let gaps = covered.complement(&Range::new(0, u64::MAX));
+        let mut by_dest: Vec<&RangeMap> = self.maps.iter().filter(|rm| rm.width > 0).collect();
+        by_dest.sort_by_key(|rm| rm.dest);
+        // unlike validate, report every pair: a wide destination can swallow several later ones
+        let mut overlaps: Vec<(RangeMap, RangeMap, Range)> = Vec::new();
+        for (i, a) in by_dest.iter().enumerate() {
+            for b in by_dest[i + 1..].iter().take_while(|b| b.dest < a.dest_range().end()) {
+                let end = a.dest_range().end().min(b.dest_range().end());
+                overlaps.push((**a, **b, Range::new(b.dest, end - b.dest)));
+            }
+        }
+        LayerCoverage{name: self.name(), maps: self.maps.clone(), covered, gaps, overlaps}
+    }
+}
+
+// how one layer treats the source axis: explicit maps, identity gaps between (and around) them,
+// and targets reached from more than one source
+#[derive(Debug)]
+struct LayerCoverage {
+    name: String,
+    maps: Vec<RangeMap>,
+    covered: RangeSet,
+    gaps: RangeSet,
+    overlaps: Vec<(RangeMap, RangeMap, Range)>
+}
+
+impl Display for LayerCoverage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}: maps: {}, covered values: {}, covered ranges: {}, identity gaps: {}, destination overlaps: {}",
+            self.name, self.maps.len(), self.covered.total_len(), self.covered.ranges().len(),
+            self.gaps.ranges().len(), self.overlaps.len())?;
+        // maps and gaps interleaved in source order; zero width maps cover nothing
+        let mut lines: Vec<(u64, String)> = self.gaps.ranges().iter().map(|g| {
+            match g.end() {
+                u64::MAX => (g.begin(), format!("  {}.. identity", g.begin())),
+                end => (g.begin(), format!("  {}..={} identity", g.begin(), end - 1))
+            }
+        }).collect();
+        lines.extend(self.maps.iter().filter(|rm| rm.width > 0).map(|rm| {
+            (rm.src, format!("  {}..={} -> {}..={}", rm.src, rm.src + (rm.width - 1), rm.dest, rm.dest + (rm.width - 1)))
+        }));
+        lines.sort_by_key(|(start, _)| *start);
+        for (_, line) in lines {
+            writeln!(f, "{}", line)?;
+        }
+        for (a, b, target) in self.overlaps.iter() {
+            writeln!(f, "  overlap: {}..={} reached from {}..={} and {}..={}", target.begin(), target.end() - 1,
+                target.begin() - a.dest + a.src, target.end() - 1 - a.dest + a.src,
+                target.begin() - b.dest + b.src, target.end() - 1 - b.dest + b.src)?;
+        }
+        Ok(())
+    }
+}
+
+fn render_warnings(warnings: &[String]) -> String {
+    warnings.iter().map(|w| format!("warning: {}\n", w)).collect()
+}
+
+fn render_coverage(almanac: &Almanac) -> String {
+    let layers = almanac.layers.iter().map(|l| l.coverage().to_string()).collect::<Vec<_>>().join("\n");
+    match almanac.warnings.is_empty() {
+        true => layers,
+        false => format!("{}\n{}", render_warnings(&almanac.warnings), layers)
+    }
+}
+
+pub fn coverage_day5(path: &str) {
+    match common::read_lines(path) {
+        Ok(lines) => match process_day5_almanac(lines.map(|l| l.unwrap())) {
+            Ok(almanac) => print!("{}", render_coverage(&almanac)),
+            Err(e) => println!("failed to read almanac: {}", e)
+        },
+        Err(e) => println!("failed to open {}: {}", path, e)
+    }
+}
+
+// the almanac can be puzzle text or a file written by compile_day5
+pub fn do_day5(path: &str) {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            println!("failed to open {}: {}", path, e);
+            return
+        }
+    };
+    let almanac = match CompiledAlmanac::load_with_warnings(&bytes) {
+        Ok((almanac, warnings)) => {
+            print!("{}", render_warnings(&warnings));
+            almanac
+        },
+        Err(e) => {
+            println!("failed to read almanac: {}", e);
+            return
+        }
+    };
+    for (part, mode) in [(1, SeedMode::Values), (2, SeedMode::StartLen)] {
+        match almanac.solve(mode) {
+            Ok((location, seed)) => println!("lowest location, part{}: {} (seed {})", part, location, seed),
+            Err(e) => println!("failed to solve part{}: {}", part, e)
+        }
+    }
+}
+
+pub fn compile_day5(input: &str, output: &str, json: bool) {
+    // warnings don't survive compiling, so this is the last chance to see them
+    let almanac = match std::fs::read(input).map(|bytes| CompiledAlmanac::load_with_warnings(&bytes)) {
+        Ok(Ok((almanac, warnings))) => {
+            print!("{}", render_warnings(&warnings));
+            almanac
+        },
+        Ok(Err(e)) => {
+            println!("failed to read almanac: {}", e);
+            return
+        },
+        Err(e) => {
+            println!("failed to open {}: {}", input, e);
+            return
+        }
+    };
+    let bytes = if json { format!("{}\n", almanac.to_json()).into_bytes() } else { almanac.to_binary() };
+    match std::fs::write(output, &bytes) {
+        Ok(()) => println!("wrote {} ({} bytes, {} pieces)", output, bytes.len(), almanac.map.pieces().len()),
+        Err(e) => println!("failed to write {}: {}", output, e)
+    }
+}
+
+fn process_day5_almanac(lines : impl Iterator<Item = String>) -> Result<Almanac, AoCError> {
     let line_groups = lines.fold(vec![Vec::new()], |mut acc: Vec<Vec<String>>, line| {
         if line.is_empty() {
             let empty_vec: Vec<String> = Vec::new();
@@ -23,7 +309,14 @@ fn process_day5_input(lines : impl Iterator<Item = String>) -> Result<(Vec<u64>,
     let res_seeds = match line_groups[0].last() {
         Some(sl) => {
             match sl.strip_prefix("seeds: ") {
-                Some(seeds) => separated_list1(multispace1, number::<u64>)(seeds).unwrap_or_default().1,
+                Some(seeds) => {
+                    let (rest, res) = separated_list1(multispace1, number::<u64>)(seeds).unwrap_or((seeds, Vec::new()));
+                    // anything left over, bar trailing whitespace, is a token that isn't a seed
+                    if let Some(bad) = rest.split_whitespace().next() {
+                        return Err(AoCError::InputValueError(format!("invalid seed '{}'", bad)))
+                    }
+                    res
+                }
                 None => return Err(AoCError::InputFormatError("expected 'seeds: ' prefix"))
             }
         }
@@ -33,18 +326,98 @@ fn process_day5_input(lines : impl Iterator<Item = String>) -> Result<(Vec<u64>,
         return Err(AoCError::InputFormatError("found no seeds or seeds list parsed incorrectly"))
     }
 
+    let mut layers: Vec<Layer> = Vec::new();
     for mg in &line_groups[1..] {
-        if mg.is_empty() || mg[0].find("map:").is_none() {
-            return Err(AoCError::InputFormatError("invalid map group - no header found"))
+        let (from, to) = match mg.first().map(|h| map_header_parser(h)) {
+            Some(Ok(("", (from, to)))) => (from.to_string(), to.to_string()),
+            Some(_) => return Err(AoCError::InputValueError(format!("invalid map header: {}", mg[0]))),
+            None => return Err(AoCError::InputFormatError("invalid map group - no header found"))
+        };
+        let maps = RangeMap::from_map_group(mg[1..].iter().map(|s| s.to_string()))
+            .map_err(|e| match e {
+                AoCError::InputValueError(msg) => AoCError::InputValueError(format!("{}-to-{} map: {}", from, to, msg)),
+                e => e
+            })?;
+        layers.push(Layer{from, to, maps});
+    }
+
+    Almanac::new(res_seeds, layers)
+}
+
+fn process_day5_input(lines : impl Iterator<Item = String>) -> Result<(Vec<u64>, Vec<Vec<RangeMap>>), AoCError> {
+    let almanac = process_day5_almanac(lines)?;
+    let maps = almanac.chain_owned("seed", "location")?;
+    Ok((almanac.seeds, maps))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SeedMode {
+    Values,
+    StartLen,
+    StartEnd
+}
+
+// how the numbers on the seeds line are read: part 1 takes each as a seed, part 2 pairs them
+// up as (start, length). (start, end) pairs use an exclusive end like Range does
+#[derive(Clone, Debug, PartialEq)]
+enum SeedSpec {
+    Values(Vec<u64>),
+    StartLen(Vec<(u64, u64)>),
+    StartEnd(Vec<(u64, u64)>)
+}
+
+impl SeedSpec {
+    fn parse(seeds: &[u64], mode: SeedMode) -> Result<SeedSpec, AoCError> {
+        if seeds.is_empty() {
+            return Err(AoCError::InputFormatError("found no seeds"))
+        }
+        if mode != SeedMode::Values && !seeds.len().is_multiple_of(2) {
+            return Err(AoCError::InputValueError(format!("seed ranges need pairs of numbers, found {} numbers", seeds.len())))
+        }
+        let pairs = || seeds.chunks(2).map(|p| (p[0], p[1]));
+        match mode {
+            SeedMode::Values => {
+                // seeds become one wide ranges, whose exclusive end can't be past u64::MAX
+                if let Some(seed) = seeds.iter().find(|s| **s == u64::MAX) {
+                    return Err(AoCError::InputValueError(format!("seed {} overflows u64", seed)))
+                }
+                Ok(SeedSpec::Values(seeds.to_vec()))
+            },
+            SeedMode::StartLen => {
+                if let Some((start, len)) = pairs().find(|(start, len)| start.checked_add(*len).is_none()) {
+                    return Err(AoCError::InputValueError(format!("seed range {} {} overflows u64", start, len)))
+                }
+                Ok(SeedSpec::StartLen(pairs().collect()))
+            },
+            SeedMode::StartEnd => {
+                if let Some((start, end)) = pairs().find(|(start, end)| end < start) {
+                    return Err(AoCError::InputValueError(format!("seed range {}..{} ends before it starts", start, end)))
+                }
+                Ok(SeedSpec::StartEnd(pairs().collect()))
+            }
         }
     }
 
-    let maps: Vec<Vec<RangeMap>> = line_groups[1..].iter().map(|mg| {
-        RangeMap::from_map_group(mg[1..].iter().map(|s| s.to_string()))
-    }).collect();
+    fn ranges(&self) -> RangeSet {
+        match self {
+            SeedSpec::Values(v) => RangeSet::from_ranges(v.iter().map(|s| Range::new(*s, 1))),
+            SeedSpec::StartLen(p) => RangeSet::from_ranges(p.iter().map(|(start, len)| Range::new(*start, *len))),
+            SeedSpec::StartEnd(p) => RangeSet::from_ranges(p.iter().map(|(start, end)| Range::new(*start, end - start)))
+        }
+    }
+}
 
-    Ok((res_seeds, maps))
+// lowest location reachable from the seeds, along with the seed that gets there
+fn lowest_location(seeds: &SeedSpec, maps: &[Vec<RangeMap>]) -> Option<(u64, u64)> {
+    ComposedMap::compose(maps).min_location(&seeds.ranges())
 }
+
+fn solve_day5(lines : impl Iterator<Item = String>, mode: SeedMode) -> Result<(u64, u64), AoCError> {
+    let (res_seeds, maps) = process_day5_input(lines)?;
+    let seeds = SeedSpec::parse(&res_seeds, mode)?;
+    lowest_location(&seeds, &maps).ok_or(AoCError::InputFormatError("seed ranges are empty"))
+}
+
 fn map_day5_part1_input(lines : impl Iterator<Item = String>) -> Result<Vec<u64>, AoCError> {
     let (res_seeds, maps) = process_day5_input(lines)?;
     let ret: Vec<u64> = res_seeds.iter().map(|s| map_with_groups(*s, &maps)).collect();
@@ -74,143 +447,875 @@ fn map_by_source(m: &Vec<RangeMap>, seek: u64) -> u64 {
         },
         None => seek
     }
-}
+}
+
+fn map_with_groups(map_val: u64, maps: &Vec<Vec<RangeMap>>) -> u64 {
+    let mut ret = map_val;
+    for m in maps {
+        ret = map_by_source(&m, ret);
+    }
+    ret
+}
+
+// every source value that one layer maps onto seek. maps need not be injective, so there
+// can be several - including seek itself when it falls outside every source range
+fn unmap_by_dest(m: &Vec<RangeMap>, seek: u64) -> Vec<u64> {
+    let mut ret: Vec<u64> = m.iter()
+        .filter(|rm| rm.dest_range().contains(seek))
+        .map(|rm| seek - rm.dest + rm.src)
+        .collect();
+    if find_by_source(m, seek).is_none() {
+        ret.push(seek);
+    }
+    ret.sort();
+    ret.dedup();
+    ret
+}
+
+fn unmap_with_groups(map_val: u64, maps: &[Vec<RangeMap>]) -> Vec<u64> {
+    let mut ret = vec![map_val];
+    for m in maps.iter().rev() {
+        ret = ret.iter().flat_map(|v| unmap_by_dest(m, *v)).collect();
+        ret.sort();
+        ret.dedup();
+    }
+    ret
+}
+
+fn unmap_set_with_groups(set: &RangeSet, maps: &[Vec<RangeMap>]) -> RangeSet {
+    maps.iter().rev().fold(set.clone(), |acc, m| acc.unmap(m))
+}
+
+// part 2 from the other end: walk locations upward in growing windows and pull each window
+// back to seeds; the first window that reaches a seed holds the lowest location
+fn lowest_location_by_inverse(seeds: &RangeSet, maps: &[Vec<RangeMap>]) -> Option<(u64, u64)> {
+    let mut lo = 0u64;
+    let mut width = 1u64;
+    loop {
+        let window = Range::new(lo, width.min(u64::MAX - lo));
+        let reached = unmap_set_with_groups(&RangeSet::from(window), maps).intersection(seeds);
+        if !reached.is_empty() {
+            // forward map what was reached to find the exact lowest location, then its seed
+            let locations = maps.iter().fold(reached, |acc, m| acc.map(m));
+            let loc = locations.intersection(&RangeSet::from(window)).min()?;
+            let seed = unmap_set_with_groups(&RangeSet::from(Range::new(loc, 1)), maps).intersection(seeds).min()?;
+            return Some((loc, seed))
+        }
+        if window.end() == u64::MAX {
+            return None
+        }
+        lo = window.end();
+        width = width.saturating_mul(2);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Range {
+    start: u64,
+    width: u64
+}
+
+impl Range {
+    fn new(start: u64, width: u64) -> Range {
+        Range{start, width}
+    }
+
+    // for ranges read from files, which must not run past u64::MAX
+    fn checked(start: u64, width: u64) -> Result<Range, AoCError> {
+        match start.checked_add(width) {
+            Some(_) => Ok(Range{start, width}),
+            None => Err(AoCError::InputValueError(format!("range {} width {} overflows u64", start, width)))
+        }
+    }
+    fn begin(&self) -> u64 {
+        self.start
+    }
+    fn end(&self) -> u64 {
+        self.start + self.width
+    }
+    fn contains(&self, v: u64) -> bool {
+        v >= self.begin() && v < self.end()
+    }
+
+    // return true if any part of self overlaps target. empty ranges overlap nothing
+    fn overlaps(&self, target: &Range) -> bool {
+        self.width > 0 && target.width > 0 && self.begin() < target.end() && target.begin() < self.end()
+    }
+
+    // return true if check spans target - ie. target is completely within check. end is exclusive
+    // for both, so a target ending exactly where self ends is still spanned
+    fn spans(&self, target: &Range) -> bool {
+        target.begin() >= self.begin() && target.end() <= self.end()
+    }
+
+    // find maps that apply (overlap or spanned) to this range
+    fn find_range_maps<'a>(&self, target: &'a Vec<RangeMap>) -> Vec<&'a RangeMap> {
+        let find: Vec<&RangeMap> = target.iter().filter(|target| {
+            self.overlaps(&target.src_range() ) || self.spans(&target.src_range())
+        }).collect();
+        find
+    }
+
+    // splits range around the map source: (unmapped part before it, mapped part, unmapped part after it).
+    // any of the three may be empty
+    fn map_range(&self, rm: &RangeMap) -> (Range, Range, Range) {
+        let src = rm.src_range();
+        let lo = self.begin().clamp(src.begin(), src.end());
+        let hi = self.end().clamp(src.begin(), src.end());
+        let before_end = min(self.end(), src.begin());
+        let after_start = self.begin().max(src.end());
+        (Range::new(self.start, before_end.saturating_sub(self.start)),
+         Range::new(lo - rm.src + rm.dest, hi - lo),
+         Range::new(after_start, self.end().saturating_sub(after_start)))
+    }
+
+    // maps must be sorted by source and non-overlapping, as produced by from_map_group
+    fn map_ranges(start_range: &Range, maps: &[RangeMap]) -> Vec<Range> {
+        let mut ret: Vec<Range> = Vec::new();
+        let mut current_range = *start_range;
+        for rm in maps {
+            if current_range.overlaps(&rm.src_range()) {
+                // anything before this map was also after the previous one, so it stays unmapped
+                let (res_before, res_mapped, res_after) = current_range.map_range(rm);
+                for r in [res_before, res_mapped] {
+                    if r.width != 0 {
+                        ret.push(r)
+                    }
+                }
+                current_range = res_after;
+            }
+        }
+        if current_range.width > 0 {
+            ret.push(current_range);
+        }
+        ret
+    }
+
+    fn map_multi_ranges(ranges: &Vec<Range>, maps: &Vec<RangeMap>) -> Vec<Range> {
+        ranges.iter().flat_map(|r| Range::map_ranges(r, maps)).collect()
+    }
+}
+
+impl From<(u64,u64)> for Range {
+    fn from(value: (u64, u64)) -> Self {
+        Range{start: value.0, width: value.1}
+    }
+}
+
+// sorted, coalesced, non-overlapping set of ranges; empty ranges are never stored
+#[derive(Clone, Debug, Default, PartialEq)]
+struct RangeSet {
+    ranges: Vec<Range>
+}
+
+impl RangeSet {
+    fn new() -> RangeSet {
+        RangeSet{ranges: Vec::new()}
+    }
+
+    fn from_ranges(ranges: impl IntoIterator<Item = Range>) -> RangeSet {
+        let mut sorted: Vec<Range> = ranges.into_iter().filter(|r| r.width > 0).collect();
+        sorted.sort_by_key(|r| r.start);
+        let mut ret: Vec<Range> = Vec::with_capacity(sorted.len());
+        for r in sorted {
+            match ret.last_mut() {
+                // touching ranges are merged as well as overlapping ones
+                Some(last) if r.begin() <= last.end() => {
+                    last.width = last.width.max(r.end() - last.start);
+                },
+                _ => ret.push(r)
+            }
+        }
+        RangeSet{ranges: ret}
+    }
+
+    fn ranges(&self) -> &[Range] {
+        &self.ranges
+    }
+    fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+    fn total_len(&self) -> u64 {
+        self.ranges.iter().map(|r| r.width).sum()
+    }
+    fn min(&self) -> Option<u64> {
+        self.ranges.first().map(|r| r.start)
+    }
+
+    fn contains(&self, v: u64) -> bool {
+        self.contains_range(&Range::new(v, 1))
+    }
+
+    // true if every value of target is in the set; an empty target is always contained
+    fn contains_range(&self, target: &Range) -> bool {
+        if target.width == 0 {
+            return true
+        }
+        let idx = self.ranges.partition_point(|r| r.end() <= target.begin());
+        match self.ranges.get(idx) {
+            Some(r) => r.begin() <= target.begin() && target.end() <= r.end(),
+            None => false
+        }
+    }
+
+    fn union(&self, other: &RangeSet) -> RangeSet {
+        RangeSet::from_ranges(self.ranges.iter().chain(other.ranges.iter()).copied())
+    }
+
+    fn intersection(&self, other: &RangeSet) -> RangeSet {
+        let mut ret: Vec<Range> = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let (a, b) = (&self.ranges[i], &other.ranges[j]);
+            let (lo, hi) = (a.begin().max(b.begin()), a.end().min(b.end()));
+            if lo < hi {
+                ret.push(Range::new(lo, hi - lo));
+            }
+            if a.end() <= b.end() { i += 1 } else { j += 1 }
+        }
+        RangeSet{ranges: ret}
+    }
+
+    fn difference(&self, other: &RangeSet) -> RangeSet {
+        let mut ret: Vec<Range> = Vec::new();
+        let mut j = 0;
+        for r in self.ranges.iter() {
+            let mut lo = r.begin();
+            while j < other.ranges.len() && other.ranges[j].end() <= lo {
+                j += 1;
+            }
+            let mut k = j;
+            while k < other.ranges.len() && other.ranges[k].begin() < r.end() {
+                let cut = &other.ranges[k];
+                if cut.begin() > lo {
+                    ret.push(Range::new(lo, cut.begin() - lo));
+                }
+                lo = lo.max(cut.end());
+                k += 1;
+            }
+            if lo < r.end() {
+                ret.push(Range::new(lo, r.end() - lo));
+            }
+        }
+        RangeSet{ranges: ret}
+    }
+
+    // everything within bound that is not in the set
+    fn complement(&self, bound: &Range) -> RangeSet {
+        RangeSet::from(*bound).difference(self)
+    }
+
+    // inverse of map: every source value that the layer sends into the set
+    fn unmap(&self, maps: &Vec<RangeMap>) -> RangeSet {
+        let sources = RangeSet::from_ranges(maps.iter().map(|rm| rm.src_range()));
+        let mut ret: Vec<Range> = self.difference(&sources).ranges;
+        for rm in maps {
+            for r in self.intersection(&RangeSet::from(rm.dest_range())).ranges {
+                ret.push(Range::new(r.start - rm.dest + rm.src, r.width));
+            }
+        }
+        RangeSet::from_ranges(ret)
+    }
+
+    // values covered by a map are moved to its destination, everything else passes through unchanged
+    fn map(&self, maps: &Vec<RangeMap>) -> RangeSet {
+        let sources = RangeSet::from_ranges(maps.iter().map(|rm| rm.src_range()));
+        let mut ret: Vec<Range> = self.difference(&sources).ranges;
+        for rm in maps {
+            for r in self.intersection(&RangeSet::from(rm.src_range())).ranges {
+                ret.push(Range::new(r.start - rm.src + rm.dest, r.width));
+            }
+        }
+        RangeSet::from_ranges(ret)
+    }
+}
+
+impl From<Range> for RangeSet {
+    fn from(value: Range) -> Self {
+        RangeSet::from_ranges([value])
+    }
+}
+
+// a whole chain of map layers folded into one sorted piecewise-linear map. pieces are
+// contiguous from 0 and include identity gaps, so lookups need a single binary search
+#[derive(Clone, Debug, PartialEq)]
+struct ComposedMap {
+    pieces: Vec<RangeMap>
+}
+
+impl ComposedMap {
+    fn identity() -> ComposedMap {
+        ComposedMap{pieces: vec![(0, 0, u64::MAX).into()]}
+    }
+
+    // pieces loaded from a compiled almanac must tile 0..u64::MAX exactly like compose produces
+    fn from_pieces(pieces: Vec<RangeMap>) -> Result<ComposedMap, AoCError> {
+        let mut pos = 0;
+        for p in pieces.iter() {
+            if p.src != pos || p.width == 0 {
+                return Err(AoCError::InputValueError(format!("composed map piece {}..{} does not continue from {}",
+                    p.src, p.src_range().end(), pos)))
+            }
+            pos = p.src_range().end();
+        }
+        if pos != u64::MAX {
+            return Err(AoCError::InputValueError(format!("composed map stops at {}", pos)))
+        }
+        Ok(ComposedMap{pieces})
+    }
+
+    // layer sources must be sorted and non-overlapping, as produced by from_map_group
+    fn compose(maps: &[Vec<RangeMap>]) -> ComposedMap {
+        maps.iter().fold(ComposedMap::identity(), |acc, layer| acc.then(layer))
+    }
+
+    // pieces of self followed by one more layer
+    fn then(&self, layer: &[RangeMap]) -> ComposedMap {
+        let index = LayerIndex::new(layer);
+        let mut pieces: Vec<RangeMap> = Vec::new();
+        for p in self.pieces.iter() {
+            let end = p.dest + p.width;
+            let mut pos = p.dest;
+            for rm in index.overlapping(&Range::new(p.dest, p.width)) {
+                if rm.src > pos {
+                    pieces.push((p.src + (pos - p.dest), pos, rm.src - pos).into());
+                    pos = rm.src;
+                }
+                // zero width maps cover nothing and must not leave an empty piece behind
+                let hi = end.min(rm.src + rm.width);
+                if hi > pos {
+                    pieces.push((p.src + (pos - p.dest), rm.dest + (pos - rm.src), hi - pos).into());
+                    pos = hi;
+                }
+            }
+            if pos < end {
+                pieces.push((p.src + (pos - p.dest), pos, end - pos).into());
+            }
+        }
+        ComposedMap{pieces: ComposedMap::merge(pieces)}
+    }
+
+    // joins neighbouring pieces that continue the same linear mapping
+    fn merge(pieces: Vec<RangeMap>) -> Vec<RangeMap> {
+        let mut ret: Vec<RangeMap> = Vec::with_capacity(pieces.len());
+        for p in pieces {
+            match ret.last_mut() {
+                Some(last) if last.src + last.width == p.src && last.dest + last.width == p.dest => {
+                    last.width += p.width
+                },
+                _ => ret.push(p)
+            }
+        }
+        ret
+    }
+
+    fn pieces(&self) -> &[RangeMap] {
+        &self.pieces
+    }
+
+    fn lookup(&self, v: u64) -> u64 {
+        let idx = self.pieces.partition_point(|p| p.src + p.width <= v);
+        match self.pieces.get(idx) {
+            Some(p) if p.src <= v => v - p.src + p.dest,
+            _ => v
+        }
+    }
+
+    // each piece maps linearly, so the lowest location within it comes from the lowest seed in it.
+    // returns (location, seed)
+    fn min_location(&self, seeds: &RangeSet) -> Option<(u64, u64)> {
+        let mut ret: Option<(u64, u64)> = None;
+        let mut idx = 0;
+        for r in seeds.ranges() {
+            let mut pos = r.begin();
+            while pos < r.end() {
+                while idx < self.pieces.len() && self.pieces[idx].src + self.pieces[idx].width <= pos {
+                    idx += 1;
+                }
+                let (loc, next) = match self.pieces.get(idx) {
+                    Some(p) if p.src <= pos => (pos - p.src + p.dest, r.end().min(p.src + p.width)),
+                    _ => (pos, r.end())
+                };
+                if ret.is_none_or(|(best, _)| loc < best) {
+                    ret = Some((loc, pos));
+                }
+                pos = next;
+            }
+        }
+        ret
+    }
+
+    // a single pass over the pieces, since both are sorted by source
+    fn map_set(&self, set: &RangeSet) -> RangeSet {
+        let mut ret: Vec<Range> = Vec::new();
+        let mut idx = 0;
+        for r in set.ranges() {
+            let mut pos = r.begin();
+            while pos < r.end() {
+                while idx < self.pieces.len() && self.pieces[idx].src + self.pieces[idx].width <= pos {
+                    idx += 1;
+                }
+                match self.pieces.get(idx) {
+                    Some(p) if p.src <= pos => {
+                        let hi = r.end().min(p.src + p.width);
+                        ret.push(Range::new(pos - p.src + p.dest, hi - pos));
+                        pos = hi;
+                    },
+                    _ => {
+                        ret.push(Range::new(pos, r.end() - pos));
+                        pos = r.end();
+                    }
+                }
+            }
+        }
+        RangeSet::from_ranges(ret)
+    }
+}
+
+impl Display for ComposedMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for p in self.pieces.iter() {
+            let last = p.src + (p.width - 1);
+            if p.src == p.dest {
+                writeln!(f, "{}..={} identity", p.src, last)?;
+            } else {
+                writeln!(f, "{}..={} -> {}..={}", p.src, last, p.dest, p.dest + (p.width - 1))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct RangeMap {
+    src: u64,
+    dest: u64,
+    width: u64
+}
+
+impl RangeMap {
+    fn checked(src: u64, dest: u64, width: u64) -> Result<RangeMap, AoCError> {
+        if src.checked_add(width).is_none() || dest.checked_add(width).is_none() {
+            return Err(AoCError::InputValueError(format!("map {} {} {} overflows u64", dest, src, width)))
+        }
+        Ok(RangeMap{src, dest, width})
+    }
+
+    fn src_range(&self) -> Range {
+        Range{start: self.src, width: self.width}
+    }
+    fn dest_range(&self) -> Range {
+        Range{start: self.dest, width: self.width}
+    }
+
+    // input is "dest source width"; returns (source, dest, width)
+    fn from_map_line(line: &str) -> Result<RangeMap, AoCError> {
+        let p = line.split_whitespace().map(u64::from_str).collect::<Result<Vec<_>, _>>()
+            .map_err(|e| AoCError::InputValueError(format!("invalid map line '{}': {}", line, e)))?;
+        match p[..] {
+            [dest, src, width] => Ok((src, dest, width).into()),
+            _ => Err(AoCError::InputValueError(format!("invalid map line '{}': expected 3 values, found {}", line, p.len())))
+        }
+    }
+
+    fn from_map_group(lines : impl Iterator<Item = String>) -> Result<Vec<RangeMap>, AoCError> {
+        let mut ret: Vec<RangeMap> = lines.take_while(|l| !l.is_empty()).map(|l| {
+            RangeMap::from_map_line(&l)
+        }).collect::<Result<Vec<_>, _>>()?;
+        ret.sort_by(|a, b| a.src.cmp(&b.src));
+        Ok(ret)
+    }
+}
+
+impl From<(u64,u64,u64)> for RangeMap {
+    fn from(value: (u64,u64,u64)) -> Self {
+        RangeMap{src: value.0, dest: value.1, width: value.2}
+    }
+}
+
+// lookup structure for very large layers. sources are non-overlapping once validated, so an
+// interval tree reduces to a sorted array. point lookups walk a copy of the maps in eytzinger
+// (bfs) order, so the first levels of every search share a few cache lines and the map is in
+// hand once the walk ends; each slot also remembers its position in the sorted maps so overlap
+// queries can return a slice of them
+#[derive(Clone, Debug)]
+struct LayerIndex {
+    sorted: Vec<RangeMap>,
+    // 1-based eytzinger layout; slot 0 is unused
+    tree: Vec<RangeMap>,
+    order: Vec<usize>
+}
+
+impl LayerIndex {
+    fn new(maps: &[RangeMap]) -> LayerIndex {
+        let mut sorted = maps.to_vec();
+        sorted.sort_by_key(|rm| rm.src);
+        let mut tree = vec![RangeMap::from((0, 0, 0)); sorted.len() + 1];
+        let mut order = vec![0usize; sorted.len() + 1];
+        let mut next = 0;
+        LayerIndex::fill(&sorted, &mut tree, &mut order, &mut next, 1);
+        LayerIndex{sorted, tree, order}
+    }
+
+    // in-order walk of the implicit tree hands out the sorted maps smallest first
+    fn fill(sorted: &[RangeMap], tree: &mut [RangeMap], order: &mut [usize], next: &mut usize, k: usize) {
+        if k <= sorted.len() {
+            LayerIndex::fill(sorted, tree, order, next, 2 * k);
+            tree[k] = sorted[*next];
+            order[k] = *next;
+            *next += 1;
+            LayerIndex::fill(sorted, tree, order, next, 2 * k + 1);
+        }
+    }
+
+    // slot reached by walking down towards v; its set bits are the right turns taken
+    fn descend(&self, v: u64) -> usize {
+        let n = self.sorted.len();
+        let mut k = 1;
+        while k <= n {
+            k = 2 * k + usize::from(self.tree[k].src <= v);
+        }
+        k
+    }
+
+    fn maps(&self) -> &[RangeMap] {
+        &self.sorted
+    }
+
+    // sorted position of the first map whose source starts after v
+    fn upper_bound(&self, v: u64) -> usize {
+        // the last left turn on the way down is the answer; none means every source is <= v
+        let k = self.descend(v);
+        let k = k >> (k.trailing_ones() + 1);
+        if k == 0 { self.sorted.len() } else { self.order[k] }
+    }
+
+    fn find(&self, v: u64) -> Option<&RangeMap> {
+        // the last right turn is the last map starting at or before v
+        let k = self.descend(v);
+        let k = k >> (k.trailing_zeros() + 1);
+        self.tree.get(k).filter(|rm| k > 0 && rm.src_range().contains(v))
+    }
+
+    fn map(&self, v: u64) -> u64 {
+        match self.find(v) {
+            Some(rm) => v - rm.src + rm.dest,
+            None => v
+        }
+    }
+
+    // maps whose sources overlap r, in source order
+    fn overlapping(&self, r: &Range) -> &[RangeMap] {
+        if r.width == 0 {
+            return &[]
+        }
+        let mut lo = self.upper_bound(r.begin());
+        if lo > 0 && self.sorted[lo - 1].src_range().overlaps(r) {
+            lo -= 1;
+        }
+        let hi = self.upper_bound(r.end() - 1);
+        &self.sorted[lo..hi]
+    }
+
+    fn map_range(&self, r: &Range) -> Vec<Range> {
+        Range::map_ranges(r, self.overlapping(r))
+    }
+}
+
+// compiled almanacs: the seeds plus the whole seed-to-location chain folded into one ComposedMap,
+// so repeated queries skip parsing and composing. the binary form is a magic tag and version
+// byte followed by LEB128 varints; the JSON form holds the same fields by name
+const COMPILED_MAGIC: &[u8] = b"AOC5";
+const COMPILED_VERSION: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+struct CompiledAlmanac {
+    seeds: Vec<u64>,
+    map: ComposedMap
+}
+
+impl CompiledAlmanac {
+    fn from_lines(lines : impl Iterator<Item = String>) -> Result<CompiledAlmanac, AoCError> {
+        let (seeds, maps) = process_day5_input(lines)?;
+        Ok(CompiledAlmanac{seeds, map: ComposedMap::compose(&maps)})
+    }
+
+    fn solve(&self, mode: SeedMode) -> Result<(u64, u64), AoCError> {
+        let seeds = SeedSpec::parse(&self.seeds, mode)?;
+        self.map.min_location(&seeds.ranges()).ok_or(AoCError::InputFormatError("seed ranges are empty"))
+    }
+
+    fn to_binary(&self) -> Vec<u8> {
+        let mut ret = COMPILED_MAGIC.to_vec();
+        ret.push(COMPILED_VERSION);
+        self.encode(&mut ret);
+        ret
+    }
+
+    fn from_binary(bytes: &[u8]) -> Result<CompiledAlmanac, AoCError> {
+        let mut input = bytes.strip_prefix(COMPILED_MAGIC).ok_or(AoCError::InputFormatError("not a compiled almanac"))?;
+        match input.split_first() {
+            Some((&COMPILED_VERSION, rest)) => input = rest,
+            Some((v, _)) => return Err(AoCError::InputValueError(format!("unsupported compiled almanac version {}", v))),
+            None => return Err(AoCError::InputFormatError("compiled almanac is truncated"))
+        }
+        let ret = CompiledAlmanac::decode(&mut input)?;
+        if !input.is_empty() {
+            return Err(AoCError::InputValueError(format!("{} unexpected bytes after compiled almanac", input.len())))
+        }
+        Ok(ret)
+    }
+
+    // whichever of the three formats the bytes hold: binary, JSON or the puzzle text
+    fn load(bytes: &[u8]) -> Result<CompiledAlmanac, AoCError> {
+        Ok(CompiledAlmanac::load_with_warnings(bytes)?.0)
+    }
+
+    // text almanacs come back with their validation warnings; compiled files have none of their own
+    fn load_with_warnings(bytes: &[u8]) -> Result<(CompiledAlmanac, Vec<String>), AoCError> {
+        if bytes.starts_with(COMPILED_MAGIC) {
+            return Ok((CompiledAlmanac::from_binary(bytes)?, Vec::new()))
+        }
+        let text = std::str::from_utf8(bytes).map_err(|_| AoCError::InputFormatError("almanac is neither compiled nor text"))?;
+        if text.trim_start().starts_with('{') {
+            return Ok((CompiledAlmanac::from_json(&Json::parse(text)?)?, Vec::new()))
+        }
+        let almanac = process_day5_almanac(text.lines().map(|l| l.to_string()))?;
+        let maps = almanac.chain_owned("seed", "location")?;
+        Ok((CompiledAlmanac{seeds: almanac.seeds, map: ComposedMap::compose(&maps)}, almanac.warnings))
+    }
+}
+
+// binary and JSON forms of the day 5 value types
+trait Compiled: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(input: &mut &[u8]) -> Result<Self, AoCError>;
+    fn to_json(&self) -> Json;
+    fn from_json(json: &Json) -> Result<Self, AoCError>;
+}
+
+impl Compiled for u64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut v = *self;
+        while v >= 0x80 {
+            out.push((v & 0x7f) as u8 | 0x80);
+            v >>= 7;
+        }
+        out.push(v as u8);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, AoCError> {
+        let mut ret = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = input.split_first().ok_or(AoCError::InputFormatError("compiled almanac is truncated"))?;
+            *input = rest;
+            let bits = u64::from(byte & 0x7f);
+            // the tenth byte only has room for the top bit
+            if shift == 63 && bits > 1 {
+                return Err(AoCError::InputFormatError("compiled value overflows u64"))
+            }
+            ret |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(ret)
+            }
+        }
+        Err(AoCError::InputFormatError("compiled value overflows u64"))
+    }
+
+    fn to_json(&self) -> Json {
+        Json::Number(*self)
+    }
+
+    fn from_json(json: &Json) -> Result<Self, AoCError> {
+        match json {
+            Json::Number(n) => Ok(*n),
+            _ => Err(AoCError::InputFormatError("expected a JSON number"))
+        }
+    }
+}
+
+impl<T: Compiled> Compiled for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u64).encode(out);
+        self.iter().for_each(|v| v.encode(out));
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, AoCError> {
+        let count = u64::decode(input)?;
+        // every value takes at least a byte, so a corrupt count can't reserve more than the input
+        let mut ret = Vec::with_capacity((count as usize).min(input.len()));
+        for _ in 0..count {
+            ret.push(T::decode(input)?);
+        }
+        Ok(ret)
+    }
+
+    fn to_json(&self) -> Json {
+        Json::Array(self.iter().map(|v| v.to_json()).collect())
+    }
+
+    fn from_json(json: &Json) -> Result<Self, AoCError> {
+        match json {
+            Json::Array(values) => values.iter().map(T::from_json).collect(),
+            _ => Err(AoCError::InputFormatError("expected a JSON array"))
+        }
+    }
+}
+
+impl Compiled for Range {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.start.encode(out);
+        self.width.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, AoCError> {
+        Range::checked(u64::decode(input)?, u64::decode(input)?)
+    }
+
+    fn to_json(&self) -> Json {
+        Json::Object(vec![("start".to_string(), self.start.to_json()), ("width".to_string(), self.width.to_json())])
+    }
 
-fn map_with_groups(map_val: u64, maps: &Vec<Vec<RangeMap>>) -> u64 {
-    let mut ret = map_val;
-    for m in maps {
-        ret = map_by_source(&m, ret);
+    fn from_json(json: &Json) -> Result<Self, AoCError> {
+        Range::checked(u64::from_json(json.field("start")?)?, u64::from_json(json.field("width")?)?)
     }
-    ret
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-struct Range {
-    start: u64,
-    width: u64
-}
+impl Compiled for RangeMap {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.src.encode(out);
+        self.dest.encode(out);
+        self.width.encode(out);
+    }
 
-impl Range {
-    fn new(start: u64, width: u64) -> Range {
-        Range{start, width}
+    fn decode(input: &mut &[u8]) -> Result<Self, AoCError> {
+        RangeMap::checked(u64::decode(input)?, u64::decode(input)?, u64::decode(input)?)
     }
-    fn begin(&self) -> u64 {
-        self.start
+
+    fn to_json(&self) -> Json {
+        Json::Object(vec![("src".to_string(), self.src.to_json()), ("dest".to_string(), self.dest.to_json()),
+            ("width".to_string(), self.width.to_json())])
     }
-    fn end(&self) -> u64 {
-        self.start + self.width
+
+    fn from_json(json: &Json) -> Result<Self, AoCError> {
+        RangeMap::checked(u64::from_json(json.field("src")?)?, u64::from_json(json.field("dest")?)?,
+            u64::from_json(json.field("width")?)?)
     }
+}
 
-    // return true if any part of self overlaps target
-    fn overlaps(&self, target: &Range) -> bool {
-        if self.begin() >= target.begin() && self.begin() < target.end() {
-            return true
-        } else if self.end() > target.begin() && self.end() <= target.end() {
-            return true
-        }
-        false
+impl Compiled for ComposedMap {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.pieces.encode(out);
     }
 
-    // return true if check spans target - ie. target is completely within check
-    fn spans(&self, target: &Range) -> bool {
-        target.begin() >= self.begin() && target.end() < self.end()
+    fn decode(input: &mut &[u8]) -> Result<Self, AoCError> {
+        ComposedMap::from_pieces(Vec::decode(input)?)
     }
 
-    // find maps that apply (overlap or spanned) to this range
-    fn find_range_maps<'a>(&self, target: &'a Vec<RangeMap>) -> Vec<&'a RangeMap> {
-        let find: Vec<&RangeMap> = target.iter().filter(|target| {
-            self.overlaps(&target.src_range() ) || self.spans(&target.src_range())
-        }).collect();
-        find
+    fn to_json(&self) -> Json {
+        Json::Object(vec![("pieces".to_string(), self.pieces.to_json())])
     }
 
-    // calculates map of range and returns 'unmapped' portion
-    fn map_range(&self, rm: &RangeMap) -> (Range, Range) {
-        if self.start >= rm.src {
-            let off = self.start - rm.src;
-            let map_width = min(rm.width - off, self.width);
-            (Range::new(rm.dest+off, map_width), Range::new(rm.src+rm.width,self.width-map_width))
-        } else if self.end() > rm.src {
-            let map_width = self.end() - rm.src;
-            (Range::new(rm.dest, map_width), Range::new(self.start,self.width-map_width))
-        } else {
-            (Range::new(0,0), Range::new(self.start,self.width))
-        }
+    fn from_json(json: &Json) -> Result<Self, AoCError> {
+        ComposedMap::from_pieces(Vec::from_json(json.field("pieces")?)?)
     }
+}
 
-    fn map_ranges(start_range: &Range, maps: &Vec<RangeMap>) -> Vec<Range> {
-        let mut ret: Vec<Range> = Vec::new();
-        let mut current_range = *start_range;
-        for rm in maps {
-            if current_range.overlaps(&rm.src_range()) || current_range.spans(&rm.src_range()) {
-                let (res_mapped, res_unmapped) = current_range.map_range(rm);
-                if res_mapped.width != 0 {
-                    ret.push(res_mapped)
-                }
-                current_range = res_unmapped;
-            }
-        }
-        if current_range.width > 0 {
-            ret.push(current_range);
-        }
-        ret
+impl Compiled for CompiledAlmanac {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.seeds.encode(out);
+        self.map.encode(out);
     }
 
-    fn map_multi_ranges(ranges: &Vec<Range>, maps: &Vec<RangeMap>) -> Vec<Range> {
-        ranges.iter().flat_map(|r| Range::map_ranges(r, maps)).collect()
+    fn decode(input: &mut &[u8]) -> Result<Self, AoCError> {
+        Ok(CompiledAlmanac{seeds: Vec::decode(input)?, map: ComposedMap::decode(input)?})
     }
-}
 
-impl From<(u64,u64)> for Range {
-    fn from(value: (u64, u64)) -> Self {
-        Range{start: value.0, width: value.1}
+    fn to_json(&self) -> Json {
+        Json::Object(vec![("seeds".to_string(), self.seeds.to_json()), ("map".to_string(), self.map.to_json())])
+    }
+
+    fn from_json(json: &Json) -> Result<Self, AoCError> {
+        Ok(CompiledAlmanac{seeds: Vec::from_json(json.field("seeds")?)?, map: ComposedMap::from_json(json.field("map")?)?})
     }
 }
 
-#[derive(PartialEq, Debug)]
-struct RangeMap {
-    src: u64,
-    dest: u64,
-    width: u64
+// just the JSON the compiled forms need: unsigned integers, arrays and objects with plain keys.
+// numbers are kept as u64, so values above 2^53 survive even though not every JSON reader would
+#[derive(Clone, Debug, PartialEq)]
+enum Json {
+    Number(u64),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>)
 }
 
-impl RangeMap {
-    fn src_range(&self) -> Range {
-        Range{start: self.src, width: self.width}
-    }
-    fn dest_range(&self) -> Range {
-        Range{start: self.dest, width: self.width}
-    }
+fn json_parser(input: &str) -> IResult<&str, Json> {
+    delimited(multispace0, alt((
+        map(map_res(digit1, u64::from_str), Json::Number),
+        map(delimited(char('['), separated_list0(char(','), json_parser), preceded(multispace0, char(']'))), Json::Array),
+        map(delimited(char('{'), separated_list0(char(','), json_member), preceded(multispace0, char('}'))), Json::Object)
+    )), multispace0)(input)
+}
 
-    // input is "dest source width"; returns (source, dest, width)
-// TODO: error handling?
-    fn from_map_line(line: String) -> RangeMap {
-        let (_, p) = separated_list1(multispace1, number::<u64>)(line.as_str()).unwrap_or_default();
-        (p[1], p[0], p[2]).into()
+fn json_member(input: &str) -> IResult<&str, (String, Json)> {
+    let key = delimited(char('"'), take_while1(|c: char| c.is_ascii_alphanumeric() || c == '_'), char('"'));
+    map(separated_pair(delimited(multispace0, key, multispace0), char(':'), json_parser), |(k, v)| (k.to_string(), v))(input)
+}
+
+impl Json {
+    fn parse(input: &str) -> Result<Json, AoCError> {
+        match json_parser(input) {
+            Ok(("", json)) => Ok(json),
+            _ => Err(AoCError::InputFormatError("invalid or unsupported JSON"))
+        }
     }
 
-    fn from_map_group(lines : impl Iterator<Item = String>) -> Vec<RangeMap> {
-        let mut ret: Vec<RangeMap> = lines.take_while(|l| !l.is_empty()).map(|l| {
-            RangeMap::from_map_line(l).into()
-        }).collect();
-        ret.sort_by(|a, b| a.src.cmp(&b.src));
-        ret
+    fn field(&self, name: &str) -> Result<&Json, AoCError> {
+        match self {
+            Json::Object(members) => members.iter().find(|(k, _)| k == name).map(|(_, v)| v)
+                .ok_or_else(|| AoCError::InputValueError(format!("missing JSON field '{}'", name))),
+            _ => Err(AoCError::InputFormatError("expected a JSON object"))
+        }
     }
 }
 
-impl From<(u64,u64,u64)> for RangeMap {
-    fn from(value: (u64,u64,u64)) -> Self {
-        RangeMap{src: value.0, dest: value.1, width: value.2}
+impl Display for Json {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Json::Number(n) => write!(f, "{}", n),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, v) in values.iter().enumerate() {
+                    write!(f, "{}{}", if i > 0 { "," } else { "" }, v)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in members.iter().enumerate() {
+                    write!(f, "{}\"{}\":{}", if i > 0 { "," } else { "" }, k, v)?;
+                }
+                write!(f, "}}")
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Instant;
     use nom::character::complete::multispace1;
     use nom::multi::separated_list1;
     use crate::common;
-    use crate::common::number;
-    use crate::day5::{find_by_source, map_by_source, map_day5_part1_input, process_day5_input, Range, RangeMap};
+    use crate::common::{AoCError, number, XorShift};
+    use crate::day5::{Almanac, Compiled, render_coverage, CompiledAlmanac, ComposedMap, Json, LayerIndex, lowest_location, SeedMode, SeedSpec, solve_day5, find_by_source, process_day5_almanac, lowest_location_by_inverse, map_by_source, map_with_groups, unmap_by_dest, unmap_set_with_groups, unmap_with_groups, map_day5_part1_input, process_day5_input, Range, RangeMap, RangeSet};
+
+    // each input with its lowest part 1 location and seed, and its lowest part 2 location
+    const ANSWERS: [(&str, (u64, u64), u64); 3] = [
+        ("./data/day5example.txt", (35, 13), 46),
+        ("./data/day5input.txt", (403695602, 1038807941), 219529182),
+        ("./data/day5input-jhrcook.txt", (650599855, 601583464), 1240035)
+    ];
 
     #[test]
     fn test_tuple_searching() {
@@ -238,7 +1343,7 @@ mod tests {
             ""
         ];
 
-        let map_seed_2_soil = RangeMap::from_map_group(test_seed_2_soil.iter().map(|s| s.to_string()));
+        let map_seed_2_soil = RangeMap::from_map_group(test_seed_2_soil.iter().map(|s| s.to_string())).unwrap();
         assert_eq!(50, map_seed_2_soil[0].src);
 
         let test_soil_2_fertilizer: Vec<&str> = vec![
@@ -248,7 +1353,7 @@ mod tests {
             ""
         ];
 
-        let map_soil_2_fertilizer = RangeMap::from_map_group(test_soil_2_fertilizer.iter().map(|s| s.to_string()));
+        let map_soil_2_fertilizer = RangeMap::from_map_group(test_soil_2_fertilizer.iter().map(|s| s.to_string())).unwrap();
         assert_eq!(0, map_soil_2_fertilizer[0].src);
 
         let res = find_by_source(&map_soil_2_fertilizer, 16);
@@ -377,12 +1482,13 @@ mod tests {
         assert_eq!(check3.len(), 1);
         assert_eq!(check3[0], (200,5).into(), "fully mapped");
 
-        // (15, 35) - overlaps just second
+        // (15, 35) - spans just second, leaving unmapped pieces either side of it
         let mut check4 = Range::map_ranges(&Range::new(15,20), &target1);
         check4.sort_by((|a, b| a.start.cmp(&b.start)));
-        assert_eq!(check4.len(), 2);
-        assert_eq!(check4[0], (15,5).into(), "unmapped piece");
-        assert_eq!(check4[1], (200,15).into(), "mapped piece");
+        assert_eq!(check4.len(), 3);
+        assert_eq!(check4[0], (15,5).into(), "unmapped piece before");
+        assert_eq!(check4[1], (30,5).into(), "unmapped piece after");
+        assert_eq!(check4[2], (200,10).into(), "mapped piece");
     }
 
     #[test]
@@ -413,4 +1519,634 @@ mod tests {
         assert_eq!(check5.len(), 1);
         assert_eq!(*check5[0], (0, 100, 10).into());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_range_set_normalizing() {
+        let set = RangeSet::from_ranges(vec![(20,5).into(), (0,10).into(), (5,3).into(), (10,2).into(), (30,0).into()]);
+        assert_eq!(vec![Range::new(0,12), Range::new(20,5)], set.ranges());
+        assert_eq!(17, set.total_len());
+        assert_eq!(Some(0), set.min());
+        assert!(set.contains(11) && !set.contains(12) && set.contains(24) && !set.contains(25));
+        assert!(set.contains_range(&(2,10).into()));
+        assert!(!set.contains_range(&(10,11).into()));
+        assert!(set.contains_range(&(100,0).into()));
+        assert!(RangeSet::new().is_empty());
+    }
+
+    #[test]
+    fn test_range_set_algebra() {
+        let a = RangeSet::from_ranges(vec![(0,10).into(), (20,10).into()]);
+        let b = RangeSet::from_ranges(vec![(5,20).into(), (40,5).into()]);
+
+        assert_eq!(vec![Range::new(0,30), Range::new(40,5)], a.union(&b).ranges());
+        assert_eq!(vec![Range::new(5,5), Range::new(20,5)], a.intersection(&b).ranges());
+        assert_eq!(vec![Range::new(0,5), Range::new(25,5)], a.difference(&b).ranges());
+        assert_eq!(vec![Range::new(10,10), Range::new(40,5)], b.difference(&a).ranges());
+        assert_eq!(vec![Range::new(10,10), Range::new(30,20)], a.complement(&(0,50).into()).ranges());
+        assert_eq!(a, a.complement(&(0,50).into()).complement(&(0,30).into()));
+        assert!(a.difference(&a).is_empty());
+        assert_eq!(a.total_len() + b.total_len(), a.union(&b).total_len() + a.intersection(&b).total_len());
+    }
+
+    #[test]
+    fn test_range_set_mapping() {
+        // (0, 10) -> 100, (20, 30) -> 200
+        let maps: Vec<RangeMap> = vec![(0,100,10).into(), (20,200,10).into()];
+
+        // spans the second map completely, leaving unmapped pieces either side
+        let check = RangeSet::from(Range::new(15,20)).map(&maps);
+        assert_eq!(vec![Range::new(15,5), Range::new(30,5), Range::new(200,10)], check.ranges());
+
+        // both maps move into one contiguous destination
+        let joined: Vec<RangeMap> = vec![(0,50,10).into(), (10,60,10).into()];
+        assert_eq!(vec![Range::new(50,20)], RangeSet::from(Range::new(0,20)).map(&joined).ranges());
+    }
+
+    #[test]
+    fn test_part2_range_sets() {
+        for (path, _, part2) in ANSWERS {
+            if let Ok(lines) = common::read_lines(path) {
+                let (res_seeds, res_maps) = process_day5_input(lines.map(|l| l.unwrap())).unwrap();
+                let mut seeds = RangeSet::from_ranges(res_seeds.chunks(2).map(|p| Range::new(p[0], p[1])));
+                for rm in &res_maps {
+                    seeds = seeds.map(rm);
+                }
+                assert_eq!(Some(part2), seeds.min());
+            }
+        }
+    }
+
+    #[test]
+    fn test_composed_map() {
+        let lines = common::read_lines("./data/day5example.txt").unwrap();
+        let (res_seeds, res_maps) = process_day5_input(lines.map(|l| l.unwrap())).unwrap();
+        let composed = ComposedMap::compose(&res_maps);
+
+        assert_eq!(vec![82, 43, 86, 35], res_seeds.iter().map(|s| composed.lookup(*s)).collect::<Vec<_>>());
+        for v in 0..200 {
+            assert_eq!(map_with_groups(v, &res_maps), composed.lookup(v), "seed {}", v);
+        }
+        assert_eq!(u64::MAX, composed.lookup(u64::MAX));
+
+        // pieces tile the domain with no gaps
+        assert_eq!(0, composed.pieces()[0].src);
+        for w in composed.pieces().windows(2) {
+            assert_eq!(w[0].src + w[0].width, w[1].src);
+        }
+        let printed = composed.to_string();
+        assert_eq!(composed.pieces().len(), printed.lines().count());
+        assert!(printed.ends_with(&format!("..={} identity\n", u64::MAX - 1)));
+    }
+
+    #[test]
+    fn test_composed_printing() {
+        // 10..=11 goes to 50..=51 and straight back, so it joins the identity piece before it
+        let composed = ComposedMap::compose(&[vec![(10,50,5).into()], vec![(50,10,2).into()]]);
+        let last = format!("52..={} identity", u64::MAX - 1);
+        let expect = vec!["0..=11 identity", "12..=14 -> 52..=54", "15..=49 identity", "50..=51 -> 10..=11", last.as_str()];
+        assert_eq!(expect, composed.to_string().lines().collect::<Vec<_>>());
+
+        // "10 200 0" is accepted with a warning and must not produce an empty piece
+        let composed = ComposedMap::compose(&[vec![(200,10,0).into(), (300,5,10).into()]]);
+        assert!(composed.pieces().iter().all(|p| p.width > 0), "{:?}", composed);
+        let last = format!("310..={} identity", u64::MAX - 1);
+        let expect = vec!["0..=299 identity", "300..=309 -> 5..=14", last.as_str()];
+        assert_eq!(expect, composed.to_string().lines().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_part2_composed() {
+        for (path, _, part2) in ANSWERS {
+            if let Ok(lines) = common::read_lines(path) {
+                let (res_seeds, res_maps) = process_day5_input(lines.map(|l| l.unwrap())).unwrap();
+                let composed = ComposedMap::compose(&res_maps);
+                let seeds = RangeSet::from_ranges(res_seeds.chunks(2).map(|p| Range::new(p[0], p[1])));
+                assert_eq!(Some(part2), composed.map_set(&seeds).min());
+                for s in res_seeds.iter() {
+                    assert_eq!(map_with_groups(*s, &res_maps), composed.lookup(*s));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_reverse_lookup() {
+        let layer: Vec<RangeMap> = RangeMap::from_map_group(["50 98 2", "52 50 48"].iter().map(|s| s.to_string())).unwrap();
+        assert_eq!(vec![79], unmap_by_dest(&layer, 81));
+        assert_eq!(vec![14], unmap_by_dest(&layer, 14));
+        // 50 and 51 are reached from 98 and 99, but are themselves mapped away
+        assert_eq!(vec![98], unmap_by_dest(&layer, 50));
+        assert_eq!(vec![99], unmap_by_dest(&layer, 51));
+
+        // two sources collapsing onto one destination
+        let merging: Vec<RangeMap> = vec![(0,100,10).into(), (20,100,10).into()];
+        assert_eq!(vec![5, 25, 105], unmap_by_dest(&merging, 105));
+        // 5 is mapped away and nothing lands on it
+        assert!(unmap_by_dest(&merging, 5).is_empty());
+        let back = RangeSet::from(Range::new(100,10)).unmap(&merging);
+        assert_eq!(vec![Range::new(0,10), Range::new(20,10), Range::new(100,10)], back.ranges());
+    }
+
+    #[test]
+    fn test_reverse_chain() {
+        let lines = common::read_lines("./data/day5example.txt").unwrap();
+        let (res_seeds, res_maps) = process_day5_input(lines.map(|l| l.unwrap())).unwrap();
+        for (seed, loc) in res_seeds.iter().zip([82, 43, 86, 35]) {
+            assert!(unmap_with_groups(loc, &res_maps).contains(seed));
+        }
+        for loc in 0..120 {
+            for seed in unmap_with_groups(loc, &res_maps) {
+                assert_eq!(loc, map_with_groups(seed, &res_maps));
+            }
+        }
+
+        // which seeds land in locations 0..50
+        let low_seeds = unmap_set_with_groups(&RangeSet::from(Range::new(0, 50)), &res_maps);
+        for seed in 0..150 {
+            assert_eq!(map_with_groups(seed, &res_maps) < 50, low_seeds.contains(seed), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn test_part2_by_inverse() {
+        for (path, _, part2) in ANSWERS {
+            if let Ok(lines) = common::read_lines(path) {
+                let (res_seeds, res_maps) = process_day5_input(lines.map(|l| l.unwrap())).unwrap();
+                let seeds = RangeSet::from_ranges(res_seeds.chunks(2).map(|p| Range::new(p[0], p[1])));
+                let (loc, seed) = lowest_location_by_inverse(&seeds, &res_maps).unwrap();
+                assert_eq!(part2, loc);
+                assert!(seeds.contains(seed));
+                assert_eq!(loc, map_with_groups(seed, &res_maps));
+            }
+        }
+        assert_eq!(None, lowest_location_by_inverse(&RangeSet::new(), &[]));
+    }
+
+    fn example_lines() -> Vec<String> {
+        common::read_lines("./data/day5example.txt").unwrap().map(|l| l.unwrap()).collect()
+    }
+
+    // splits the example into its seeds line and map groups, each group without trailing blank
+    fn example_groups() -> (String, Vec<Vec<String>>) {
+        let lines = example_lines();
+        let groups: Vec<Vec<String>> = lines[2..].split(|l| l.is_empty()).map(|g| g.to_vec()).collect();
+        (lines[0].clone(), groups)
+    }
+
+    fn join_groups(seeds: &str, groups: &[Vec<String>]) -> Vec<String> {
+        let mut ret = vec![seeds.to_string()];
+        for g in groups {
+            ret.push(String::new());
+            ret.extend(g.iter().cloned());
+        }
+        ret
+    }
+
+    #[test]
+    fn test_almanac_categories() {
+        let almanac = process_day5_almanac(example_lines().into_iter()).unwrap();
+        assert_eq!(8, almanac.categories().len());
+        assert_eq!(7, almanac.chain("seed", "location").unwrap().len());
+
+        // soil 81 -> fertilizer 81 -> water 81 -> light 74 -> temperature 78 -> humidity 78
+        let soil_to_humidity = almanac.chain_owned("soil", "humidity").unwrap();
+        assert_eq!(5, soil_to_humidity.len());
+        assert_eq!(78, map_with_groups(81, &soil_to_humidity));
+        assert_eq!(0, almanac.chain("water", "water").unwrap().len());
+
+        assert!(almanac.chain("humidity", "soil").is_err());
+        assert!(almanac.chain("seed", "gravel").is_err());
+    }
+
+    #[test]
+    fn test_almanac_any_order() {
+        let (seeds, mut groups) = example_groups();
+        groups.reverse();
+        groups.swap(1, 4);
+        let res = map_day5_part1_input(join_groups(&seeds, &groups).into_iter());
+        assert_eq!(vec![82, 43, 86, 35], res.unwrap());
+    }
+
+    #[test]
+    fn test_almanac_errors() {
+        let (seeds, groups) = example_groups();
+
+        let mut missing = groups.clone();
+        missing.remove(3);
+        let res = process_day5_input(join_groups(&seeds, &missing).into_iter());
+        assert!(matches!(res, Err(AoCError::InputValueError(msg)) if msg == "no chain of maps from seed to location"));
+
+        let mut cycle = groups.clone();
+        cycle.push(vec!["humidity-to-light map:".to_string(), "1 2 3".to_string()]);
+        let res = process_day5_almanac(join_groups(&seeds, &cycle).into_iter());
+        assert!(matches!(res, Err(AoCError::InputValueError(msg)) if msg == "category maps form a cycle: light -> temperature -> humidity -> light"));
+
+        let mut duplicate = groups.clone();
+        duplicate.push(groups[0].clone());
+        assert!(process_day5_almanac(join_groups(&seeds, &duplicate).into_iter()).is_err());
+
+        let mut bad_header = groups.clone();
+        bad_header[2][0] = "fertilizer to water map:".to_string();
+        let res = process_day5_almanac(join_groups(&seeds, &bad_header).into_iter());
+        assert!(matches!(res, Err(AoCError::InputValueError(msg)) if msg == "invalid map header: fertilizer to water map:"));
+    }
+
+    #[test]
+    fn test_map_line_errors() {
+        assert_eq!(RangeMap::from((98, 50, 2)), RangeMap::from_map_line("50 98 2").unwrap());
+        let cases = vec![
+            ("50 98", "invalid map line '50 98': expected 3 values, found 2"),
+            ("", "invalid map line '': expected 3 values, found 0"),
+            ("50 98 2 7", "invalid map line '50 98 2 7': expected 3 values, found 4"),
+            ("50 x 2", "invalid map line '50 x 2': invalid digit found in string"),
+            ("50 98 18446744073709551616", "invalid map line '50 98 18446744073709551616': number too large to fit in target type")
+        ];
+        for (line, expect) in cases {
+            let res = RangeMap::from_map_line(line);
+            assert!(matches!(res, Err(AoCError::InputValueError(ref msg)) if msg == expect), "{:?}", res);
+        }
+    }
+
+    #[test]
+    fn test_almanac_validation() {
+        let (seeds, groups) = example_groups();
+        let almanac = process_day5_almanac(join_groups(&seeds, &groups).into_iter()).unwrap();
+        assert!(almanac.warnings.is_empty());
+
+        let with_group = |idx: usize, lines: Vec<&str>| {
+            let mut modified = groups.clone();
+            modified[idx].extend(lines.iter().map(|l| l.to_string()));
+            process_day5_almanac(join_groups(&seeds, &modified).into_iter())
+        };
+        let expect_err = |res: Result<Almanac, AoCError>, expect: &str| {
+            assert!(matches!(res, Err(AoCError::InputValueError(ref msg)) if msg == expect), "{:?}", res);
+        };
+
+        expect_err(with_group(0, vec!["1 2"]), "seed-to-soil map: invalid map line '1 2': expected 3 values, found 2");
+        expect_err(with_group(0, vec!["0 99 5"]), "seed-to-soil map: source ranges 98..100 and 99..104 overlap");
+        expect_err(with_group(1, vec!["0 18446744073709551610 10"]), "soil-to-fertilizer map: map 0 18446744073709551610 10 overflows u64");
+        expect_err(with_group(1, vec!["18446744073709551610 200 10"]), "soil-to-fertilizer map: map 18446744073709551610 200 10 overflows u64");
+
+        // zero width maps never overlap, in either line order
+        for lines in [vec!["500 100 50", "0 100 0"], vec!["0 100 0", "500 100 50"], vec!["500 100 50", "0 120 0"]] {
+            let almanac = with_group(1, lines.clone()).unwrap();
+            assert!(almanac.warnings.iter().any(|w| w.ends_with("has zero width")), "{:?}", lines);
+        }
+        expect_err(with_group(1, vec!["500 100 50", "0 120 5"]), "soil-to-fertilizer map: source ranges 100..150 and 120..125 overlap");
+        expect_err(with_group(1, vec!["0 120 5", "500 100 50"]), "soil-to-fertilizer map: source ranges 100..150 and 120..125 overlap");
+
+        let warned = with_group(0, vec!["10 200 0", "300 300 5", "60 400 5"]).unwrap();
+        assert_eq!(vec![
+            "seed-to-soil map: map 10 200 0 has zero width",
+            "seed-to-soil map: map 300 300 5 maps onto itself",
+            "seed-to-soil map: destination ranges 52..100 and 60..65 overlap"], warned.warnings);
+    }
+
+    fn round_trip<T: Compiled + PartialEq + std::fmt::Debug>(value: &T) {
+        let mut bytes: Vec<u8> = Vec::new();
+        value.encode(&mut bytes);
+        let mut input = &bytes[..];
+        assert_eq!(value, &T::decode(&mut input).unwrap());
+        assert!(input.is_empty());
+        assert_eq!(value, &T::from_json(&Json::parse(&value.to_json().to_string()).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_compiled_round_trip() {
+        for v in [0u64, 1, 127, 128, 300, 1 << 53, u64::MAX - 1, u64::MAX] {
+            round_trip(&v);
+        }
+        let mut bytes: Vec<u8> = Vec::new();
+        127u64.encode(&mut bytes);
+        128u64.encode(&mut bytes);
+        u64::MAX.encode(&mut bytes);
+        assert_eq!(1 + 2 + 10, bytes.len());
+
+        round_trip(&Range::new(79, 14));
+        round_trip(&vec![Range::new(0, 0), Range::new(u64::MAX - 1, 1)]);
+        round_trip(&RangeMap::from((98, 50, 2)));
+        round_trip(&ComposedMap::identity());
+
+        let almanac = CompiledAlmanac::from_lines(example_lines().into_iter()).unwrap();
+        round_trip(&almanac);
+        assert_eq!(almanac, CompiledAlmanac::from_binary(&almanac.to_binary()).unwrap());
+        assert_eq!(r#"{"start":79,"width":14}"#, Range::new(79, 14).to_json().to_string());
+        assert_eq!(r#"{"pieces":[{"src":0,"dest":0,"width":18446744073709551615}]}"#, ComposedMap::identity().to_json().to_string());
+
+        let spaced = "{ \"seeds\" : [ 79 , 14 ],\n  \"map\": {\"pieces\": [{\"width\": 18446744073709551615, \"dest\": 0, \"src\": 0}]} }\n";
+        assert_eq!(CompiledAlmanac{seeds: vec![79, 14], map: ComposedMap::identity()}, CompiledAlmanac::load(spaced.as_bytes()).unwrap());
+        assert_eq!(Json::Array(vec![]), Json::parse("[ ]").unwrap());
+    }
+
+    #[test]
+    fn test_compiled_load() {
+        let lines = example_lines();
+        let almanac = CompiledAlmanac::from_lines(lines.iter().cloned()).unwrap();
+        let text = lines.join("\n");
+        for bytes in [text.into_bytes(), almanac.to_binary(), almanac.to_json().to_string().into_bytes()] {
+            let loaded = CompiledAlmanac::load(&bytes).unwrap();
+            assert_eq!(almanac, loaded);
+            assert_eq!((35, 13), loaded.solve(SeedMode::Values).unwrap());
+            assert_eq!(46, loaded.solve(SeedMode::StartLen).unwrap().0);
+        }
+        if let Ok(lines) = common::read_lines("./data/day5input.txt") {
+            let lines: Vec<String> = lines.map(|l| l.unwrap()).collect();
+            let loaded = CompiledAlmanac::load(&CompiledAlmanac::from_lines(lines.iter().cloned()).unwrap().to_binary()).unwrap();
+            for mode in [SeedMode::Values, SeedMode::StartLen] {
+                assert_eq!(solve_day5(lines.iter().cloned(), mode).unwrap(), loaded.solve(mode).unwrap());
+            }
+        }
+
+        // a zero width map only warns when parsed, so its compiled form has to load too
+        let (seeds, mut groups) = example_groups();
+        groups[0].push("10 200 0".to_string());
+        let text = join_groups(&seeds, &groups).join("\n");
+        let (almanac, warnings) = CompiledAlmanac::load_with_warnings(text.as_bytes()).unwrap();
+        assert_eq!(vec!["seed-to-soil map: map 10 200 0 has zero width"], warnings);
+        assert!(CompiledAlmanac::load_with_warnings(&almanac.to_binary()).unwrap().1.is_empty());
+        assert_eq!(almanac, CompiledAlmanac::load(&almanac.to_binary()).unwrap());
+        assert_eq!(almanac, CompiledAlmanac::load(almanac.to_json().to_string().as_bytes()).unwrap());
+        assert_eq!((35, 13), almanac.solve(SeedMode::Values).unwrap());
+    }
+
+    #[test]
+    fn test_compiled_errors() {
+        let binary = CompiledAlmanac::from_lines(example_lines().into_iter()).unwrap().to_binary();
+        assert!(matches!(CompiledAlmanac::from_binary(&binary[..binary.len() - 1]),
+            Err(AoCError::InputFormatError("compiled almanac is truncated"))));
+        let mut trailing = binary.clone();
+        trailing.push(0);
+        assert!(matches!(CompiledAlmanac::from_binary(&trailing),
+            Err(AoCError::InputValueError(msg)) if msg == "1 unexpected bytes after compiled almanac"));
+        let mut version = binary.clone();
+        version[4] = 9;
+        assert!(matches!(CompiledAlmanac::from_binary(&version),
+            Err(AoCError::InputValueError(msg)) if msg == "unsupported compiled almanac version 9"));
+        assert!(CompiledAlmanac::from_binary(b"AOC").is_err());
+
+        let mut input: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02];
+        assert!(matches!(u64::decode(&mut input), Err(AoCError::InputFormatError("compiled value overflows u64"))));
+        let mut bytes: Vec<u8> = Vec::new();
+        vec![u64::MAX, 2u64].encode(&mut bytes);
+        assert!(matches!(Range::decode(&mut &bytes[1..]),
+            Err(AoCError::InputValueError(msg)) if msg == "range 18446744073709551615 width 2 overflows u64"));
+
+        let gap = r#"{"pieces":[{"src":0,"dest":5,"width":10},{"src":11,"dest":11,"width":18446744073709551604}]}"#;
+        assert!(matches!(ComposedMap::from_json(&Json::parse(gap).unwrap()),
+            Err(AoCError::InputValueError(msg)) if msg == "composed map piece 11..18446744073709551615 does not continue from 10"));
+        let short = r#"{"pieces":[{"src":0,"dest":5,"width":10}]}"#;
+        assert!(matches!(ComposedMap::from_json(&Json::parse(short).unwrap()),
+            Err(AoCError::InputValueError(msg)) if msg == "composed map stops at 10"));
+        assert!(matches!(RangeMap::from_json(&Json::parse(r#"{"src":0,"width":10}"#).unwrap()),
+            Err(AoCError::InputValueError(msg)) if msg == "missing JSON field 'dest'"));
+        for bad in ["{", "[1,]", r#"{"a":-1}"#, r#"{"a":1.5}"#, "[99999999999999999999]", "[1] 2"] {
+            assert!(Json::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_layer_coverage() {
+        let (seeds, mut groups) = example_groups();
+        let almanac = process_day5_almanac(join_groups(&seeds, &groups).into_iter()).unwrap();
+        let coverage = almanac.layers[0].coverage();
+        assert_eq!(RangeSet::from(Range::new(50, 50)), coverage.covered);
+        assert_eq!(vec![Range::new(0, 50), Range::new(100, u64::MAX - 100)], coverage.gaps.ranges());
+        assert!(coverage.overlaps.is_empty());
+        assert_eq!(vec![
+            "seed-to-soil map: maps: 2, covered values: 50, covered ranges: 1, identity gaps: 2, destination overlaps: 0",
+            "  0..=49 identity",
+            "  50..=97 -> 52..=99",
+            "  98..=99 -> 50..=51",
+            "  100.. identity"], coverage.to_string().lines().collect::<Vec<_>>());
+
+        // one wide destination swallowing two others: each pair is reported, not just neighbours
+        groups[0].extend(["0 200 100", "10 400 5", "20 500 5"].iter().map(|l| l.to_string()));
+        let almanac = process_day5_almanac(join_groups(&seeds, &groups).into_iter()).unwrap();
+        let coverage = almanac.layers[0].coverage();
+        assert_eq!(vec![Range::new(0, 50), Range::new(100, 100), Range::new(300, 100), Range::new(405, 95), Range::new(505, u64::MAX - 505)],
+            coverage.gaps.ranges());
+        let targets: Vec<Range> = coverage.overlaps.iter().map(|o| o.2).collect();
+        assert_eq!(vec![Range::new(10, 5), Range::new(20, 5), Range::new(50, 2), Range::new(52, 48)], targets);
+        let report = render_coverage(&almanac);
+        assert!(report.starts_with("warning: seed-to-soil map: destination ranges 0..100 and 10..15 overlap\n"), "{}", report);
+        let rendered = coverage.to_string();
+        assert!(rendered.contains("  overlap: 10..=14 reached from 210..=214 and 400..=404\n"), "{}", rendered);
+        assert!(rendered.contains("  overlap: 52..=99 reached from 252..=299 and 50..=97\n"), "{}", rendered);
+        assert!(rendered.contains("  405..=499 identity\n"), "{}", rendered);
+
+        // every source value is either in a map or in a gap, never both
+        for (i, l) in almanac.layers.iter().enumerate().skip(1) {
+            let c = l.coverage();
+            assert!(c.covered.intersection(&c.gaps).is_empty(), "layer {}", i);
+            assert_eq!(u64::MAX, c.covered.total_len() + c.gaps.total_len(), "layer {}", i);
+        }
+    }
+
+    // reference evaluator: maps every value individually, returning the sorted distinct results
+    fn brute_force_map(ranges: &[Range], maps: &[Vec<RangeMap>]) -> Vec<u64> {
+        let mut ret: Vec<u64> = ranges.iter()
+            .flat_map(|r| r.begin()..r.end())
+            .map(|v| map_with_groups(v, &maps.to_vec()))
+            .collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
+    fn expand(ranges: &[Range]) -> Vec<u64> {
+        let mut ret: Vec<u64> = ranges.iter().flat_map(|r| r.begin()..r.end()).collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
+    // generated ranges and layers for the oracle tests
+    struct Generator(XorShift);
+
+    impl Generator {
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0.below(bound)
+        }
+
+        fn range(&mut self, bound: u64, max_width: u64) -> Range {
+            Range::new(self.next(bound), self.next(max_width + 1))
+        }
+
+        // non-overlapping sources with arbitrary (possibly overlapping) destinations
+        fn layer(&mut self, bound: u64) -> Vec<RangeMap> {
+            let mut ret: Vec<RangeMap> = Vec::new();
+            let mut pos = self.next(10);
+            while pos < bound && ret.len() < 5 {
+                let width = 1 + self.next(12);
+                ret.push((pos, self.next(bound), width).into());
+                pos += width + self.next(8);
+            }
+            ret
+        }
+
+        // n sorted, non-overlapping sources with gaps, as a synthetic almanac layer would have
+        fn big_layer(&mut self, n: usize, max_width: u64) -> Vec<RangeMap> {
+            let mut pos = 0;
+            (0..n).map(|_| {
+                let (gap, width) = (self.next(max_width), 1 + self.next(max_width));
+                let rm: RangeMap = (pos + gap, self.next(u64::MAX / 2), width).into();
+                pos += gap + width;
+                rm
+            }).collect()
+        }
+    }
+
+    #[test]
+    fn test_layer_index() {
+        let mut g = Generator(XorShift(0xD1B54A32D192ED03));
+        for n in [0, 1, 2, 3, 7, 8, 100] {
+            let layer = g.big_layer(n, 10);
+            let mut shuffled = layer.clone();
+            shuffled.reverse();
+            let index = LayerIndex::new(&shuffled);
+            assert_eq!(&layer[..], index.maps());
+            let bound = layer.last().map_or(20, |rm| rm.src + rm.width + 5);
+            for v in 0..bound {
+                assert_eq!(find_by_source(&layer, v).map(|i| &layer[i]), index.find(v), "{} in {:?}", v, layer);
+                assert_eq!(map_by_source(&layer, v), index.map(v));
+            }
+            for _ in 0..200 {
+                let r = g.range(bound, 30);
+                let expect: Vec<&RangeMap> = layer.iter().filter(|rm| rm.src_range().overlaps(&r)).collect();
+                assert_eq!(expect, index.overlapping(&r).iter().collect::<Vec<_>>(), "{:?} in {:?}", r, layer);
+                assert_eq!(Range::map_ranges(&r, &layer), index.map_range(&r));
+            }
+        }
+        let index = LayerIndex::new(&[(10, 100, 5).into(), (u64::MAX - 5, 0, 5).into()]);
+        assert_eq!(4, index.map(u64::MAX - 1));
+        assert_eq!(u64::MAX, index.map(u64::MAX));
+        assert_eq!(vec![Range::new(104, 1), Range::new(15, 5)], index.map_range(&Range::new(14, 6)));
+    }
+
+    // cargo test --release bench_layer_index -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_layer_index() {
+        let mut g = Generator(XorShift(0x9E3779B97F4A7C15));
+        let layer = g.big_layer(500_000, 1000);
+        let bound = layer.last().map_or(0, |rm| rm.src + rm.width);
+        let index = LayerIndex::new(&layer);
+
+        let seeks: Vec<u64> = (0..5_000_000).map(|_| g.next(bound)).collect();
+        let start = Instant::now();
+        let searched: u64 = seeks.iter().map(|v| map_by_source(&layer, *v)).fold(0, u64::wrapping_add);
+        let searched_time = start.elapsed();
+        let start = Instant::now();
+        let indexed: u64 = seeks.iter().map(|v| index.map(*v)).fold(0, u64::wrapping_add);
+        let indexed_time = start.elapsed();
+        assert_eq!(searched, indexed);
+        println!("point lookups - binary search: {:?}, eytzinger: {:?} ({:.1}x)", searched_time, indexed_time,
+            searched_time.as_secs_f64() / indexed_time.as_secs_f64());
+
+        let ranges: Vec<Range> = (0..2_000).map(|_| g.range(bound, 50_000)).collect();
+        let start = Instant::now();
+        let scanned = Range::map_multi_ranges(&ranges, &layer);
+        let scanned_time = start.elapsed();
+        let start = Instant::now();
+        let indexed: Vec<Range> = ranges.iter().flat_map(|r| index.map_range(r)).collect();
+        let indexed_time = start.elapsed();
+        assert_eq!(scanned, indexed);
+        println!("range mapping - linear scan: {:?}, eytzinger: {:?} ({:.1}x)", scanned_time, indexed_time,
+            scanned_time.as_secs_f64() / indexed_time.as_secs_f64());
+    }
+
+    #[test]
+    fn test_range_predicates_oracle() {
+        let mut g = Generator(XorShift(0x9E3779B97F4A7C15));
+        for _ in 0..2000 {
+            let (a, b) = (g.range(30, 10), g.range(30, 10));
+            let (va, vb) = (expand(&[a]), expand(&[b]));
+            let common = va.iter().any(|v| vb.contains(v));
+            assert_eq!(common, a.overlaps(&b), "{:?} overlaps {:?}", a, b);
+            if b.width > 0 {
+                assert_eq!(vb.iter().all(|v| va.contains(v)), a.spans(&b), "{:?} spans {:?}", a, b);
+            }
+        }
+        assert!(Range::new(0,10).spans(&Range::new(5,5)), "shared exclusive end");
+        assert!(Range::new(0,10).overlaps(&Range::new(2,3)), "target strictly inside");
+        assert!(!Range::new(0,10).overlaps(&Range::new(10,3)), "touching only");
+    }
+
+    #[test]
+    fn test_map_ranges_oracle() {
+        let mut g = Generator(XorShift(0x2545F4914F6CDD1D));
+        for _ in 0..500 {
+            let layers: Vec<Vec<RangeMap>> = (0..1 + g.next(4)).map(|_| {
+                let mut layer = g.layer(80);
+                layer.sort_by_key(|rm| rm.src);
+                layer
+            }).collect();
+            let seeds: Vec<Range> = (0..1 + g.next(3)).map(|_| g.range(80, 20)).collect();
+
+            let single = Range::map_multi_ranges(&seeds, &layers[0]);
+            assert_eq!(brute_force_map(&seeds, &layers[..1]), expand(&single), "{:?} through {:?}", seeds, layers[0]);
+            for r in seeds.iter() {
+                // every value maps to exactly one value, so no width is gained or lost
+                let mapped = Range::map_ranges(r, &layers[0]);
+                assert_eq!(r.width, mapped.iter().map(|m| m.width).sum::<u64>(), "{:?} through {:?}", r, layers[0]);
+            }
+
+            let expect = brute_force_map(&seeds, &layers);
+            let mut chained = seeds.clone();
+            for layer in layers.iter() {
+                chained = Range::map_multi_ranges(&chained, layer);
+            }
+            assert_eq!(expect, expand(&chained), "{:?} through {:?}", seeds, layers);
+
+            let set = layers.iter().fold(RangeSet::from_ranges(seeds.clone()), |acc, layer| acc.map(layer));
+            assert_eq!(expect, expand(set.ranges()));
+            let composed = ComposedMap::compose(&layers);
+            assert_eq!(expect, expand(composed.map_set(&RangeSet::from_ranges(seeds.clone())).ranges()));
+        }
+    }
+
+    #[test]
+    fn test_seed_spec() {
+        assert_eq!(SeedSpec::Values(vec![79, 14, 55]), SeedSpec::parse(&[79, 14, 55], SeedMode::Values).unwrap());
+        assert_eq!(SeedSpec::StartLen(vec![(79, 14), (55, 13)]), SeedSpec::parse(&[79, 14, 55, 13], SeedMode::StartLen).unwrap());
+        assert_eq!(vec![Range::new(55, 13), Range::new(79, 14)],
+            SeedSpec::parse(&[79, 14, 55, 13], SeedMode::StartLen).unwrap().ranges().ranges());
+        assert_eq!(vec![Range::new(10, 5)], SeedSpec::parse(&[10, 15], SeedMode::StartEnd).unwrap().ranges().ranges());
+
+        let odd = SeedSpec::parse(&[79, 14, 55], SeedMode::StartLen);
+        assert!(matches!(odd, Err(AoCError::InputValueError(msg)) if msg == "seed ranges need pairs of numbers, found 3 numbers"));
+        assert!(SeedSpec::parse(&[79, 14, 55], SeedMode::StartEnd).is_err());
+        assert!(SeedSpec::parse(&[15, 10], SeedMode::StartEnd).is_err());
+        assert!(SeedSpec::parse(&[u64::MAX, 2], SeedMode::StartLen).is_err());
+        assert!(SeedSpec::parse(&[], SeedMode::Values).is_err());
+        let max = SeedSpec::parse(&[79, u64::MAX], SeedMode::Values);
+        assert!(matches!(max, Err(AoCError::InputValueError(msg)) if msg == "seed 18446744073709551615 overflows u64"));
+        let lines = ["seeds: 18446744073709551615".to_string(), String::new(), "seed-to-location map:".to_string(), "0 0 1".to_string()];
+        assert!(solve_day5(lines.iter().cloned(), SeedMode::Values).is_err());
+        assert!(CompiledAlmanac::from_lines(lines.iter().cloned()).unwrap().solve(SeedMode::Values).is_err());
+
+        // the whole seeds line has to be numbers, though trailing whitespace is fine
+        let with_seeds = |seeds: &str| [seeds.to_string(), String::new(), "seed-to-location map:".to_string(), "0 0 1".to_string()];
+        for (seeds, bad) in [("seeds: 79 14 x 55 13", "x"), ("seeds: 79 14 55 13 junk", "junk"), ("seeds: 79 -14", "-14")] {
+            let res = process_day5_input(with_seeds(seeds).into_iter());
+            assert!(matches!(res, Err(AoCError::InputValueError(msg)) if msg == format!("invalid seed '{}'", bad)), "{}", seeds);
+        }
+        assert_eq!(vec![79, 14, 55, 13], process_day5_input(with_seeds("seeds: 79 14 55 13  ").into_iter()).unwrap().0);
+    }
+
+    #[test]
+    fn test_solve_day5() {
+        for (path, part1, part2) in ANSWERS {
+            if let Ok(lines) = common::read_lines(path) {
+                let lines: Vec<String> = lines.map(|l| l.unwrap()).collect();
+                assert_eq!(part1, solve_day5(lines.iter().cloned(), SeedMode::Values).unwrap());
+
+                let (loc, seed) = solve_day5(lines.iter().cloned(), SeedMode::StartLen).unwrap();
+                assert_eq!(part2, loc);
+                let (_, maps) = process_day5_input(lines.iter().cloned()).unwrap();
+                assert_eq!(loc, map_with_groups(seed, &maps));
+            }
+        }
+        assert_eq!(None, lowest_location(&SeedSpec::StartLen(vec![(5, 0)]), &[]));
+    }
+}
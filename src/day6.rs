@@ -1,16 +1,240 @@
+use std::str::FromStr;
+use nom::branch::alt;
+use nom::bytes::complete::tag;
+use nom::character::complete::{space0, space1};
+use nom::IResult;
+use nom::multi::separated_list1;
+use nom::sequence::{delimited, preceded, tuple};
+use num_bigint::BigUint;
+use crate::common;
+use crate::common::{AoCError, number};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Race {
+    time: u64,
+    distance: u64
+}
+
+impl Race {
+    // holding the button for h ms leaves time - h ms to travel at h mm/ms, so wins are the h with
+    // h * (time - h) > distance: the integers strictly between the roots of h^2 - time*h + distance.
+    // u128 holds time^2 for any u64 time, and the count is at most time - 1
+    fn ways_to_win(&self) -> u64 {
+        let (time, distance) = (self.time as u128, self.distance as u128);
+        let travelled = |hold: u128| hold * (time - hold);
+        let Some(disc) = (time * time).checked_sub(4 * distance).filter(|d| *d > 0) else {
+            return 0
+        };
+        // (time - isqrt) / 2 is the lower root rounded down, or one past it
+        let mut lo = (time - disc.isqrt()) / 2;
+        if travelled(lo) <= distance {
+            lo += 1;
+        }
+        if 2 * lo > time { 0 } else { (time - 2 * lo + 1) as u64 }
+    }
+}
+
+// the same count for races too long for u64, such as kerned rows with many digits
+fn ways_to_win_big(time: &BigUint, distance: &BigUint) -> BigUint {
+    let square = time * time;
+    let four_d = distance * 4u32;
+    if square <= four_d {
+        return BigUint::from(0u32)
+    }
+    let mut lo: BigUint = (time - (square - four_d).sqrt()) / 2u32;
+    if &lo * (time - &lo) <= *distance {
+        lo += 1u32;
+    }
+    let twice = &lo * 2u32;
+    if twice > *time { BigUint::from(0u32) } else { time - twice + 1u32 }
+}
+
+// "Time:      7  15   30" -> (label, [7, 15, 30])
+fn row_parser(input: &str) -> IResult<&str, (&str, Vec<u64>)> {
+    tuple((
+        delimited(space0, alt((tag("Time"), tag("Distance"))), tag(":")),
+        preceded(space1, separated_list1(space1, number::<u64>)),
+        space0
+    ))(input).map(|(remaining, res)| (remaining, (res.0, res.1)))
+}
+
+// the same row read with bad kerning: every digit after the label belongs to one number, which
+// may well be longer than u64
+fn kerned_row(line: &str) -> BigUint {
+    let digits: String = line.split_once(':').map_or("", |(_, values)| values).split_whitespace().collect();
+    // rows have already been parsed as digit columns, so this can't fail
+    BigUint::from_str(&digits).unwrap_or_default()
+}
+
+// the Time and Distance lines, checked to hold the same number of columns
+fn read_rows(lines: impl Iterator<Item = String>) -> Result<(Vec<String>, Vec<Vec<u64>>), AoCError> {
+    let lines: Vec<String> = lines.filter(|l| !l.trim().is_empty()).collect();
+    if lines.len() != 2 {
+        return Err(AoCError::InputValueError(format!("expected a Time and a Distance line, found {} lines", lines.len())))
+    }
+    let mut rows = Vec::new();
+    for (line, label) in lines.iter().zip(["Time", "Distance"]) {
+        match row_parser(line) {
+            Ok(("", (found, values))) if found == label => rows.push(values),
+            _ => return Err(AoCError::InputValueError(format!("invalid {} row: {}", label, line)))
+        }
+    }
+    if rows[0].len() != rows[1].len() {
+        return Err(AoCError::InputValueError(format!("Time has {} entries but Distance has {}", rows[0].len(), rows[1].len())))
+    }
+    Ok((lines, rows))
+}
+
+fn process_day6_input(lines: impl Iterator<Item = String>, kerning: bool) -> Result<Vec<Race>, AoCError> {
+    let (lines, rows) = read_rows(lines)?;
+    if kerning {
+        let kerned = |idx: usize, label: &str| u64::try_from(kerned_row(&lines[idx])).map_err(|_| {
+            AoCError::InputValueError(format!("{} row '{}' is not a single u64 when kerned", label, lines[idx]))
+        });
+        return Ok(vec![Race{time: kerned(0, "Time")?, distance: kerned(1, "Distance")?}])
+    }
+    Ok(rows[0].iter().zip(rows[1].iter()).map(|(time, distance)| Race{time: *time, distance: *distance}).collect())
+}
+
+// part 2 without the u64 limit: races that fit take the u128 path, anything longer uses BigUint
+fn process_kerned(lines: impl Iterator<Item = String>) -> Result<BigUint, AoCError> {
+    let (lines, _) = read_rows(lines)?;
+    let (time, distance) = (kerned_row(&lines[0]), kerned_row(&lines[1]));
+    match (u64::try_from(&time), u64::try_from(&distance)) {
+        (Ok(time), Ok(distance)) => Ok(BigUint::from(Race{time, distance}.ways_to_win())),
+        _ => Ok(ways_to_win_big(&time, &distance))
+    }
+}
+
+// the product of many u64 counts easily passes u64
+fn process_races(races: &[Race]) -> BigUint {
+    races.iter().map(|r| BigUint::from(r.ways_to_win())).product()
+}
+
+pub fn do_day6() {
+    if let Ok(lines) = common::read_lines("./data/day6input.txt") {
+        let lines: Vec<String> = lines.map(|l| l.unwrap()).collect();
+        match process_day6_input(lines.iter().cloned(), false) {
+            Ok(races) => println!("ways to win, part1: {}", process_races(&races)),
+            Err(e) => println!("failed to read races: {}", e)
+        }
+        match process_kerned(lines.iter().cloned()) {
+            Ok(ways) => println!("ways to win, part2: {}", ways),
+            Err(e) => println!("failed to read races: {}", e)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::str::FromStr;
+    use num_bigint::BigUint;
+    use crate::common;
+    use crate::common::{AoCError, XorShift};
+    use crate::day6::{process_day6_input, process_kerned, process_races, Race, ways_to_win_big};
+
+    fn lines(input: &str) -> impl Iterator<Item = String> + '_ {
+        input.lines().map(|l| l.to_string())
+    }
+
     #[test]
-    fn test_part1_example() {
-        // let races = vec![(7,9), (15,40), (30,200)];
-        // let races = vec![(59,597), (79,1234), (65,1032), (75,1328)];
-        let races = vec![(59796575u64,597123410321328u64)];
-        let mut prod = 1;
-        for (race_t, race_d) in races {
-            let cnt = (0..=race_t).filter(|idx| (race_t - idx) * idx > race_d).count();
-            prod *= cnt;
-        }
-        // assert_eq!(288, prod);
-        assert_eq!(34454850, prod);
-    }
-}
\ No newline at end of file
+    fn test_race_parsing() {
+        let example = "Time:      7  15   30\nDistance:  9  40  200";
+        assert_eq!(vec![Race{time: 7, distance: 9}, Race{time: 15, distance: 40}, Race{time: 30, distance: 200}],
+            process_day6_input(lines(example), false).unwrap());
+        assert_eq!(vec![Race{time: 71530, distance: 940200}], process_day6_input(lines(example), true).unwrap());
+        // trailing whitespace and blank lines are fine
+        assert_eq!(3, process_day6_input(lines("Time: 7 15 30  \n\nDistance: 9 40 200\n"), false).unwrap().len());
+
+        let uneven = process_day6_input(lines("Time: 7 15 30\nDistance: 9 40"), false);
+        assert!(matches!(uneven, Err(AoCError::InputValueError(msg)) if msg == "Time has 3 entries but Distance has 2"));
+        let missing = process_day6_input(lines("Time: 7 15 30"), true);
+        assert!(matches!(missing, Err(AoCError::InputValueError(msg)) if msg == "expected a Time and a Distance line, found 1 lines"));
+        let kerned = process_day6_input(lines("Time: 1844674407 3709551616\nDistance: 9 40"), true);
+        assert!(matches!(kerned, Err(AoCError::InputValueError(msg)) if msg == "Time row 'Time: 1844674407 3709551616' is not a single u64 when kerned"));
+        for bad in ["Distance: 9 40 200\nTime: 7 15 30", "Time: 7 x 30\nDistance: 9 40 200", "Time: 7 15 30\nDistance: 9 40 200\nTime: 1",
+                    "Time:\nDistance:", "Time: 7 -15\nDistance: 9 40", "Time: 99999999999999999999\nDistance: 9"] {
+            assert!(process_day6_input(lines(bad), false).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_day6() {
+        let tests = vec![
+            ("./data/day6example.txt", 288u32, 71503u32),
+            ("./data/day6input.txt", 220320, 34454850)
+        ];
+        for tt in tests {
+            if let Ok(input) = common::read_lines(tt.0) {
+                let input: Vec<String> = input.map(|l| l.unwrap()).collect();
+                assert_eq!(BigUint::from(tt.1), process_races(&process_day6_input(input.iter().cloned(), false).unwrap()), "{}", tt.0);
+                assert_eq!(BigUint::from(tt.2), process_races(&process_day6_input(input.iter().cloned(), true).unwrap()), "{}", tt.0);
+                assert_eq!(BigUint::from(tt.2), process_kerned(input.iter().cloned()).unwrap(), "{}", tt.0);
+            }
+        }
+    }
+
+    // the old approach, trying every hold time
+    fn brute_force(race: &Race) -> u64 {
+        (0..=race.time).filter(|hold| {
+            (race.time - hold).checked_mul(*hold).is_none_or(|d| d > race.distance)
+        }).count() as u64
+    }
+
+    #[test]
+    fn test_ways_to_win() {
+        assert_eq!(4, Race{time: 7, distance: 9}.ways_to_win());
+        assert_eq!(0, Race{time: 4, distance: 4}.ways_to_win());
+        assert_eq!(0, Race{time: 0, distance: 0}.ways_to_win());
+        assert_eq!(1, Race{time: 2, distance: 0}.ways_to_win());
+
+        // every record around each exact square and product, where rounding would go wrong
+        for time in 0..80u64 {
+            for distance in 0..=(time * time / 4 + 2) {
+                let race = Race{time, distance};
+                assert_eq!(brute_force(&race), race.ways_to_win(), "{:?}", race);
+                assert_eq!(BigUint::from(race.ways_to_win()), ways_to_win_big(&BigUint::from(time), &BigUint::from(distance)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_ways_to_win_extremes() {
+        let max = u64::MAX;
+        assert_eq!(max - 1, Race{time: max, distance: 0}.ways_to_win());
+        // a hold of 1 only covers max - 1, so the interval is 2..=max - 2
+        assert_eq!(max - 3, Race{time: max, distance: max}.ways_to_win());
+        assert_eq!(max - 1, Race{time: max, distance: max - 2}.ways_to_win());
+        let mut rng = XorShift(0x853C49E6748FEA9B);
+        for _ in 0..10_000 {
+            let time = rng.next_u64();
+            let distance = time.rotate_left(17) >> (time % 64);
+            let race = Race{time, distance};
+            let ways = race.ways_to_win();
+            assert_eq!(BigUint::from(ways), ways_to_win_big(&BigUint::from(time), &BigUint::from(distance)), "{:?}", race);
+            // the winning interval is symmetric, so check both of its lower edges exactly
+            if ways > 0 {
+                let lo = (time - ways).div_ceil(2) as u128;
+                let (t, d) = (time as u128, distance as u128);
+                assert!(lo * (t - lo) > d && (lo - 1) * (t - lo + 1) <= d, "{:?}", race);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ways_to_win_big() {
+        // far beyond u64: check the edges of the winning interval directly
+        let time = BigUint::from_str("123456789012345678901234567890").unwrap();
+        let distance = BigUint::from_str("98765432109876543210987654321098765432109876543210").unwrap();
+        let ways = ways_to_win_big(&time, &distance);
+        let lo: BigUint = (&time + 1u32 - &ways) / 2u32;
+        assert!(&lo * (&time - &lo) > distance);
+        assert!((&lo - 1u32) * (&time - &lo + 1u32) <= distance);
+        assert_eq!(BigUint::from(0u32), ways_to_win_big(&time, &(&time * &time)));
+
+        let kerned = "Time: 1234567890 1234567890 1234567890\nDistance: 9 8 7";
+        assert!(process_day6_input(kerned.lines().map(|l| l.to_string()), true).is_err());
+        let time = BigUint::from_str("123456789012345678901234567890").unwrap();
+        assert_eq!(&time - 1u32, process_kerned(kerned.lines().map(|l| l.to_string())).unwrap());
+    }
+}
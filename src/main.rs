@@ -11,11 +11,44 @@ mod day6;
 mod day7;
 mod day8;
 
-use crate::day3::do_day3;
-use crate::day4::do_day4;
+use std::env;
+use std::path::Path;
+use crate::day3::{do_day3, explain_day3};
+use crate::day4::{do_day4, explain_day4, parse_scoring};
+use crate::day5::{compile_day5, coverage_day5, do_day5};
+use crate::day6::do_day6;
 
+// usage: adventofcode2023 [day3|day4|day5|day6] [--explain] [--scoring=<rule>] [--coverage] [--input=<path>];
+//        adventofcode2023 day5 compile [--input=<path>] [--output=<path>] [--json]
+// runs day 4 when no day is given. day 5 reads either the puzzle text or a compiled almanac
 fn main() {
-    do_day4()
+    let args: Vec<String> = env::args().skip(1).collect();
+    let explain = args.iter().any(|a| a == "--explain");
+    let coverage = args.iter().any(|a| a == "--coverage");
+    let almanac = args.iter().find_map(|a| a.strip_prefix("--input=")).unwrap_or("./data/day5input.txt");
+    let scoring_rule = args.iter().find_map(|a| a.strip_prefix("--scoring=")).unwrap_or("doubling");
+    let scoring = match parse_scoring(scoring_rule) {
+        Ok(scoring) => scoring,
+        Err(e) => {
+            println!("invalid scoring: {}", e);
+            return
+        }
+    };
+    let json = args.iter().any(|a| a == "--json");
+    let output = args.iter().find_map(|a| a.strip_prefix("--output=")).map(|o| o.to_string())
+        .unwrap_or_else(|| Path::new(almanac).with_extension(if json { "json" } else { "bin" }).display().to_string());
+    let mut commands = args.iter().filter(|a| !a.starts_with("--")).map(|a| a.as_str());
+    let day = commands.next().unwrap_or("day4");
+    let command = commands.next();
+    match (day, explain) {
+        ("day3", false) => do_day3(),
+        ("day3", true) => explain_day3(),
+        ("day4", false) => do_day4(scoring.as_ref()),
+        ("day4", true) => explain_day4(scoring.as_ref()),
+        ("day5", _) if command == Some("compile") => compile_day5(almanac, &output, json),
+        ("day5", _) if coverage => coverage_day5(almanac),
+        ("day5", _) => do_day5(almanac),
+        ("day6", _) => do_day6(),
+        _ => println!("unknown day: {}", day)
+    }
 }
-
-